@@ -0,0 +1,295 @@
+//! The places album art is fetched from, tried in the order of --art-priority.
+
+use std::{
+    cell::Cell,
+    io::Read,
+    process::{Command, Stdio},
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use log::{debug, warn};
+use mpd::Song;
+
+use crate::{discogs, lastfm, musicbrainz, spotify::Spotify, MpdClient};
+
+/// A place album art can be fetched from.
+pub trait ArtSource: Send {
+    /// The album art of `song`, or `None` if there is none or it couldn't be fetched.
+    fn fetch(&self, song: &Song) -> Option<Vec<u8>>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ArtSourceKind {
+    /// MPD's albumart command, or else its readpicture command for embedded art
+    Mpd,
+    /// The --art-fetch-hook command
+    Hook,
+    /// The Cover Art Archive, with --musicbrainz-art
+    Musicbrainz,
+    /// Discogs, with --discogs-art
+    Discogs,
+    /// Spotify, with --spotify-art
+    Spotify,
+    /// Last.fm, with --last-fm-art
+    Lastfm,
+}
+
+impl ArtSourceKind {
+    /// The order sources are tried in without --art-priority. Of the web services, the Cover
+    /// Art Archive comes first since it matches releases exactly.
+    pub fn default_priority(prefer_mpd_art: bool, prefer_remote_art: bool) -> Vec<ArtSourceKind> {
+        use ArtSourceKind::*;
+        let local = match prefer_mpd_art {
+            true => [Mpd, Hook],
+            false => [Hook, Mpd],
+        };
+        let remote = [Musicbrainz, Discogs, Spotify, Lastfm];
+        match prefer_remote_art {
+            true => remote.into_iter().chain(local).collect(),
+            false => local.into_iter().chain(remote).collect(),
+        }
+    }
+}
+
+/// Which sources album art is fetched from, in what order, and the options for fetching it.
+#[derive(Clone)]
+pub struct ArtSources {
+    pub priority: Vec<ArtSourceKind>,
+    /// The --art-fetch-hook command.
+    pub hook: Option<String>,
+    /// Largest art, or web service response, accepted from any source.
+    pub max_bytes: usize,
+    /// Key for Last.fm's API, if art is looked up there.
+    pub lastfm_api_key: Option<String>,
+    /// Whether art is looked up on the Cover Art Archive.
+    pub musicbrainz_art: bool,
+    /// Token for Discogs's API, if art is looked up there.
+    pub discogs_token: Option<String>,
+    /// Spotify's API, if art is looked up there.
+    pub spotify: Option<Arc<Spotify>>,
+}
+
+impl ArtSources {
+    /// The enabled sources, in order of priority, with `mpd_art` as MPD's art.
+    fn sources<'a>(&'a self, mut mpd_art: Option<Vec<u8>>) -> Vec<Box<dyn ArtSource + 'a>> {
+        self.priority
+            .iter()
+            .filter_map(|kind| -> Option<Box<dyn ArtSource + 'a>> {
+                match kind {
+                    ArtSourceKind::Mpd => Some(Box::new(MpdArt(Cell::new(mpd_art.take())))),
+                    ArtSourceKind::Hook => Some(Box::new(HookArt(self.hook.as_deref()?))),
+                    ArtSourceKind::Musicbrainz => self
+                        .musicbrainz_art
+                        .then_some(Box::new(MusicBrainzArt(self.max_bytes))),
+                    ArtSourceKind::Discogs => Some(Box::new(DiscogsArt(
+                        self.discogs_token.as_deref()?,
+                        self.max_bytes,
+                    ))),
+                    ArtSourceKind::Spotify => Some(Box::new(SpotifyArt(
+                        self.spotify.as_deref()?,
+                        self.max_bytes,
+                    ))),
+                    ArtSourceKind::Lastfm => Some(Box::new(LastFmArt(
+                        self.lastfm_api_key.as_deref()?,
+                        self.max_bytes,
+                    ))),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Fetches the album art of `song` from each of the enabled sources in order of priority,
+/// returning the first that has any.
+pub fn fetch_art(client: &mut MpdClient, song: &Song, sources: &ArtSources) -> Option<Vec<u8>> {
+    let mpd_art = fetch_mpd_art(client, song, sources);
+    fetch_art_with_mpd_art(song, sources, mpd_art)
+}
+
+/// Fetches MPD's album art of `song` through `client`, if MPD is one of the sources: the cover
+/// file in the song's directory, or else the picture embedded in the song. It is fetched ahead
+/// of the others, whatever its priority, so that the connection can be given back before the
+/// slower sources are tried. Art larger than the limit is ignored.
+pub fn fetch_mpd_art(client: &mut MpdClient, song: &Song, sources: &ArtSources) -> Option<Vec<u8>> {
+    if !sources.priority.contains(&ArtSourceKind::Mpd) {
+        return None;
+    }
+    client
+        .albumart(song)
+        .inspect_err(|err| warn!("error fetching album art for \"{}\": {:?}", song.file, err))
+        .ok()
+        .filter(|bytes| !bytes.is_empty())
+        .or_else(|| {
+            client
+                .readpicture(song)
+                .inspect_err(|err| {
+                    warn!("error reading embedded art of \"{}\": {:?}", song.file, err)
+                })
+                .ok()
+                .filter(|bytes| !bytes.is_empty())
+        })
+        .filter(|bytes| {
+            let fits = bytes.len() <= sources.max_bytes;
+            if !fits {
+                warn!(
+                    "ignoring {} bytes of album art for \"{}\", more than the limit of {}",
+                    bytes.len(),
+                    song.file,
+                    sources.max_bytes
+                );
+            }
+            fits
+        })
+}
+
+/// Like `fetch_art`, but with MPD's art already fetched by `fetch_mpd_art`, so that no MPD
+/// connection is needed.
+pub fn fetch_art_with_mpd_art(
+    song: &Song,
+    sources: &ArtSources,
+    mpd_art: Option<Vec<u8>>,
+) -> Option<Vec<u8>> {
+    sources
+        .sources(mpd_art)
+        .iter()
+        .find_map(|source| source.fetch(song))
+}
+
+/// MPD's art, fetched ahead of time by `fetch_mpd_art`.
+struct MpdArt(Cell<Option<Vec<u8>>>);
+
+impl ArtSource for MpdArt {
+    fn fetch(&self, _song: &Song) -> Option<Vec<u8>> {
+        self.0.take()
+    }
+}
+
+/// The art written by an --art-fetch-hook command.
+struct HookArt<'a>(&'a str);
+
+impl HookArt<'_> {
+    /// How long the command may run before it is killed.
+    const TIMEOUT: Duration = Duration::from_secs(30);
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+}
+
+impl ArtSource for HookArt<'_> {
+    /// Runs the command with the shell, with MPD_FILE set to the file of `song`, and returns
+    /// what it writes to its standard output, if it succeeds in time and writes anything.
+    fn fetch(&self, song: &Song) -> Option<Vec<u8>> {
+        let cmd = self.0;
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .env("MPD_FILE", &song.file)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .inspect_err(|err| warn!("error running art fetch hook `{}`: {:?}", cmd, err))
+            .ok()?;
+        // Read while waiting, so that the command can't block on a full pipe.
+        let stdout = read_pipe(child.stdout.take());
+        let stderr = read_pipe(child.stderr.take());
+        let deadline = Instant::now() + Self::TIMEOUT;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if Instant::now() < deadline => std::thread::sleep(Self::POLL_INTERVAL),
+                Ok(None) => {
+                    warn!(
+                        "art fetch hook `{}` took longer than {:?}; killing it",
+                        cmd,
+                        Self::TIMEOUT
+                    );
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return None;
+                }
+                Err(err) => {
+                    warn!("error waiting for art fetch hook `{}`: {:?}", cmd, err);
+                    return None;
+                }
+            }
+        };
+        let stdout = stdout.join().unwrap_or_default();
+        if !status.success() {
+            let stderr = stderr.join().unwrap_or_default();
+            warn!(
+                "art fetch hook `{}` failed with {}: {}",
+                cmd,
+                status,
+                String::from_utf8_lossy(&stderr).trim()
+            );
+            None
+        } else if stdout.is_empty() {
+            debug!("art fetch hook `{}` found no art for {:?}", cmd, song.file);
+            None
+        } else {
+            Some(stdout)
+        }
+    }
+}
+
+/// Reads everything from `pipe` on its own thread.
+fn read_pipe(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+/// The Cover Art Archive, with the largest response accepted.
+struct MusicBrainzArt(usize);
+
+impl ArtSource for MusicBrainzArt {
+    fn fetch(&self, song: &Song) -> Option<Vec<u8>> {
+        musicbrainz::fetch_art(song, self.0)
+            .inspect_err(|err| warn!("error fetching album art from Cover Art Archive: {:?}", err))
+            .ok()
+            .flatten()
+    }
+}
+
+/// Discogs, with a personal access token and the largest response accepted.
+struct DiscogsArt<'a>(&'a str, usize);
+
+impl ArtSource for DiscogsArt<'_> {
+    fn fetch(&self, song: &Song) -> Option<Vec<u8>> {
+        discogs::fetch_art(self.0, song, self.1)
+            .inspect_err(|err| warn!("error fetching album art from Discogs: {:?}", err))
+            .ok()
+            .flatten()
+    }
+}
+
+/// Spotify, with the largest response accepted.
+struct SpotifyArt<'a>(&'a Spotify, usize);
+
+impl ArtSource for SpotifyArt<'_> {
+    fn fetch(&self, song: &Song) -> Option<Vec<u8>> {
+        self.0
+            .fetch_art(song, self.1)
+            .inspect_err(|err| warn!("error fetching album art from Spotify: {:?}", err))
+            .ok()
+            .flatten()
+    }
+}
+
+/// Last.fm, with an API key and the largest response accepted.
+struct LastFmArt<'a>(&'a str, usize);
+
+impl ArtSource for LastFmArt<'_> {
+    fn fetch(&self, song: &Song) -> Option<Vec<u8>> {
+        lastfm::fetch_art(self.0, song, self.1)
+            .inspect_err(|err| warn!("error fetching album art from Last.fm: {:?}", err))
+            .ok()
+            .flatten()
+    }
+}
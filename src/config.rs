@@ -0,0 +1,75 @@
+//! The optional TOML config file.
+
+use std::{collections::HashMap, path::Path, path::PathBuf};
+
+use log::info;
+use serde::Deserialize;
+
+use crate::Result;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Key for each action whose default binding is replaced, e.g. `quit = "ctrl+c"`.
+    pub keybindings: HashMap<String, String>,
+    pub colors: Colors,
+    /// Crop solid-color borders from the album art, as with --auto-crop, unless
+    /// --no-auto-crop is given.
+    pub auto_crop: bool,
+}
+
+/// Colors replacing those of the theme, e.g. `border = "#5f87af"`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Colors {
+    pub border: Option<String>,
+    pub title: Option<String>,
+    pub error: Option<String>,
+    pub warning: Option<String>,
+}
+
+impl Config {
+    /// Reads the config from `path`, or from the default location if no path is given. A
+    /// missing file is only an error if its path was given explicitly.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let (path, required) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Config::default())
+            }
+            Err(err) => return Err(format!("error reading {}: {}", path.display(), err).into()),
+        };
+        info!("loading config from {}", path.display());
+        toml::from_str(&contents)
+            .map_err(|err| format!("error parsing {}: {}", path.display(), err).into())
+    }
+
+    /// `$XDG_CONFIG_HOME/mpd-album-art-viewer/config.toml`, falling back to `~/.config`.
+    fn default_path() -> Option<PathBuf> {
+        let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_home.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_auto_crop() {
+        let config: Config = toml::from_str("auto_crop = true").unwrap();
+        assert!(config.auto_crop);
+        let config: Config = toml::from_str("").unwrap();
+        assert!(!config.auto_crop);
+    }
+}
@@ -0,0 +1,83 @@
+//! Album art from Discogs, for --discogs-art.
+
+use log::{debug, info};
+use mpd::Song;
+
+use crate::{http, song_tag, Result};
+
+const API_URL: &str = "https://api.discogs.com";
+
+/// Fetches the primary image of the release of `song` from Discogs, by the release ID it's
+/// tagged with if any, or else by searching for its artist and album. Responses larger than
+/// `max_bytes` are an error.
+pub fn fetch_art(token: &str, song: &Song, max_bytes: usize) -> Result<Option<Vec<u8>>> {
+    if let Some(release_id) = song_tag(song, "DISCOGS_RELEASE_ID") {
+        return fetch_release_art(release_id, token, max_bytes).map(Some);
+    }
+    let Some(artist) = song_tag(song, "AlbumArtist").or(song.artist.as_deref()) else {
+        return Ok(None);
+    };
+    let Some(album) = song_tag(song, "Album") else {
+        return Ok(None);
+    };
+    fetch_discogs_art(artist, album, token, max_bytes).map(Some)
+}
+
+/// Fetches the cover image of the first release Discogs finds by `artist` named `album`.
+pub fn fetch_discogs_art(
+    artist: &str,
+    album: &str,
+    token: &str,
+    max_bytes: usize,
+) -> Result<Vec<u8>> {
+    let url = format!(
+        "{}/database/search?type=release&artist={}&release_title={}&token={}",
+        API_URL,
+        http::encode_query_value(artist),
+        http::encode_query_value(album),
+        http::encode_query_value(token)
+    );
+    let results = get_json(&url, max_bytes)?;
+    let image_url = results["results"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|result| result["cover_image"].as_str())
+        .find(|url| !url.is_empty())
+        .ok_or_else(|| format!("Discogs has no release {} - {}", artist, album))?;
+    debug!("found {} - {} on Discogs", artist, album);
+    info!("fetching album art from {}", image_url);
+    http::get(image_url, max_bytes)
+}
+
+/// Fetches the primary image of the Discogs release `release_id`, or its first image if none
+/// is marked primary.
+fn fetch_release_art(release_id: &str, token: &str, max_bytes: usize) -> Result<Vec<u8>> {
+    let url = format!(
+        "{}/releases/{}?token={}",
+        API_URL,
+        http::encode_query_value(release_id),
+        http::encode_query_value(token)
+    );
+    let release = get_json(&url, max_bytes)?;
+    let images = release["images"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let image_url = images
+        .iter()
+        .find(|image| image["type"] == "primary")
+        .or(images.first())
+        .and_then(|image| image["uri"].as_str())
+        .ok_or_else(|| format!("Discogs release {} has no images", release_id))?;
+    info!("fetching album art from {}", image_url);
+    http::get(image_url, max_bytes)
+}
+
+fn get_json(url: &str, max_bytes: usize) -> Result<serde_json::Value> {
+    let value: serde_json::Value = serde_json::from_slice(&http::get(url, max_bytes)?)?;
+    if let Some(message) = value["message"].as_str() {
+        return Err(format!("Discogs error: {}", message).into());
+    }
+    Ok(value)
+}
@@ -0,0 +1,143 @@
+//! The --art-effects pipeline: image adjustments applied in the order they are listed.
+
+use image::{DynamicImage, Rgba};
+
+use crate::{image_processing, parse_f32_between};
+
+/// An adjustment to album art, given on the command line as NAME or NAME:VALUE.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageEffect {
+    /// Gamma correction; values above 1 brighten the midtones.
+    Gamma(f32),
+    /// Added to each channel, from -255 to 255.
+    Brightness(i32),
+    /// Percentage to raise the contrast by, or lower it by if negative.
+    Contrast(f32),
+    /// Factor to scale the saturation by; 0 is grayscale.
+    Saturation(f32),
+    /// Degrees to rotate the hue by.
+    Hue(i32),
+    /// An unsharp mask, as with --sharpen.
+    Sharpen(f32),
+    /// A Gaussian blur with the given standard deviation in pixels.
+    Blur(f32),
+    Grayscale,
+    Invert,
+}
+
+/// Parses one effect of an --art-effects list, e.g. "gamma:2.0" or "invert".
+pub fn parse_effect(s: &str) -> Result<ImageEffect, String> {
+    let (name, value) = match s.split_once(':') {
+        Some((name, value)) => (name, Some(value)),
+        None => (s, None),
+    };
+    let raw_value = || value.ok_or_else(|| format!("{} needs a value, e.g. {}:1.5", name, name));
+    let value = || -> Result<f32, String> {
+        let value = raw_value()?;
+        match value.parse::<f32>() {
+            Ok(parsed) if parsed.is_finite() => Ok(parsed),
+            Ok(_) => Err(format!("{:?} is not a finite number", value)),
+            Err(err) => Err(format!("{:?}: {}", value, err)),
+        }
+    };
+    let effect = match name {
+        "gamma" => ImageEffect::Gamma(value()?),
+        "brightness" => ImageEffect::Brightness(value()?.clamp(-255.0, 255.0) as i32),
+        "contrast" => ImageEffect::Contrast(value()?),
+        "saturation" => ImageEffect::Saturation(value()?),
+        "hue" => ImageEffect::Hue(value()? as i32),
+        // The same range as --sharpen.
+        "sharpen" => ImageEffect::Sharpen(parse_f32_between(raw_value()?, 0.0, 5.0)?),
+        "blur" => ImageEffect::Blur(value()?),
+        "grayscale" => ImageEffect::Grayscale,
+        "invert" => ImageEffect::Invert,
+        _ => {
+            return Err(format!(
+                "unknown effect {:?}; expected gamma, brightness, contrast, saturation, hue, \
+                 sharpen, blur, grayscale or invert",
+                name
+            ))
+        }
+    };
+    match effect {
+        ImageEffect::Gamma(gamma) if gamma <= 0.0 => Err("gamma must be positive".into()),
+        ImageEffect::Saturation(factor) if factor < 0.0 => {
+            Err("saturation can't be negative".into())
+        }
+        ImageEffect::Blur(sigma) if sigma < 0.0 => Err("blur can't be negative".into()),
+        _ => Ok(effect),
+    }
+}
+
+/// Applies `effects` to `img` in order.
+pub fn apply_effects(img: DynamicImage, effects: &[ImageEffect]) -> DynamicImage {
+    effects.iter().fold(img, |img, effect| match *effect {
+        ImageEffect::Gamma(gamma) => {
+            let table: Vec<u8> = (0..=255)
+                .map(|v| (255.0 * (v as f32 / 255.0).powf(1.0 / gamma)).round() as u8)
+                .collect();
+            map_rgb(&img, |[r, g, b]| {
+                [table[r as usize], table[g as usize], table[b as usize]]
+            })
+        }
+        ImageEffect::Brightness(value) => img.brighten(value),
+        ImageEffect::Contrast(percent) => img.adjust_contrast(percent),
+        ImageEffect::Saturation(factor) => map_rgb(&img, |[r, g, b]| {
+            let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+            let saturate = |c: u8| (luma + (c as f32 - luma) * factor).clamp(0.0, 255.0) as u8;
+            [saturate(r), saturate(g), saturate(b)]
+        }),
+        ImageEffect::Hue(degrees) => img.huerotate(degrees),
+        ImageEffect::Sharpen(amount) => image_processing::sharpen(img, amount),
+        ImageEffect::Blur(sigma) if sigma > 0.0 => img.blur(sigma),
+        ImageEffect::Blur(_) => img,
+        ImageEffect::Grayscale => img.grayscale(),
+        ImageEffect::Invert => {
+            let mut img = img;
+            img.invert();
+            img
+        }
+    })
+}
+
+/// `img` with `f` applied to the red, green and blue of each pixel, keeping the alpha.
+fn map_rgb(img: &DynamicImage, f: impl Fn([u8; 3]) -> [u8; 3]) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    for Rgba([r, g, b, _]) in rgba.pixels_mut() {
+        [*r, *g, *b] = f([*r, *g, *b]);
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_effect_values() {
+        assert_eq!(parse_effect("gamma:2.0"), Ok(ImageEffect::Gamma(2.0)));
+        assert_eq!(
+            parse_effect("brightness:300"),
+            Ok(ImageEffect::Brightness(255))
+        );
+        assert_eq!(parse_effect("sharpen:1.5"), Ok(ImageEffect::Sharpen(1.5)));
+        assert_eq!(parse_effect("invert"), Ok(ImageEffect::Invert));
+    }
+
+    #[test]
+    fn parse_effect_rejects_bad_values() {
+        for s in [
+            "gamma",
+            "gamma:0",
+            "gamma:nan",
+            "contrast:inf",
+            "saturation:-1",
+            "blur:-0.5",
+            "sharpen:6",
+            "sharpen:-1",
+            "sepia",
+        ] {
+            assert!(parse_effect(s).is_err(), "{} parsed", s);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Transcoding of connections to MPD servers whose tags are not UTF-8, for --encoding.
+//!
+//! The mpd crate rejects any line that isn't valid UTF-8, so tags have to be decoded on the way
+//! in, before it sees them, and paths sent back to the server encoded again on the way out.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+};
+
+use clap::ValueEnum;
+use encoding_rs::WINDOWS_1252;
+use log::debug;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TagEncoding {
+    #[default]
+    #[value(name = "UTF-8")]
+    Utf8,
+    /// Latin-1, decoded as its Windows-1252 superset
+    #[value(name = "LATIN-1")]
+    Latin1,
+}
+
+/// The socket under an `MpdStream`.
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<crate::tls::TlsStream>),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A connection to MPD that converts its text to and from `encoding`. Album art and other
+/// binary chunks are passed through untouched.
+pub struct MpdStream {
+    reader: BufReader<Connection>,
+    encoding: TagEncoding,
+    /// The current line from the server, converted to UTF-8, and how much of it has been read.
+    line: Vec<u8>,
+    line_pos: usize,
+    /// Bytes of a binary chunk still to be passed through.
+    binary_remaining: usize,
+    /// The command being written, converted from UTF-8 when it is flushed.
+    command: Vec<u8>,
+}
+
+impl MpdStream {
+    pub fn new(stream: Connection, encoding: TagEncoding) -> MpdStream {
+        MpdStream {
+            reader: BufReader::new(stream),
+            encoding,
+            line: Vec::new(),
+            line_pos: 0,
+            binary_remaining: 0,
+            command: Vec::new(),
+        }
+    }
+
+    /// Reads the next line from the server into `self.line`, converting it to UTF-8.
+    fn read_line(&mut self) -> std::io::Result<()> {
+        let mut raw = Vec::new();
+        self.reader.read_until(b'\n', &mut raw)?;
+        let (line, had_errors) = WINDOWS_1252.decode_without_bom_handling(&raw);
+        if had_errors {
+            debug!("lossy conversion of {:?} from {:?}", line, self.encoding);
+        }
+        self.binary_remaining = line
+            .strip_prefix("binary: ")
+            .and_then(|len| len.trim_end().parse().ok())
+            .unwrap_or(0);
+        self.line = line.into_owned().into_bytes();
+        self.line_pos = 0;
+        Ok(())
+    }
+}
+
+impl Read for MpdStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.encoding == TagEncoding::Utf8 {
+            return self.reader.read(buf);
+        }
+        if self.line_pos == self.line.len() {
+            if self.binary_remaining > 0 {
+                let len = buf.len().min(self.binary_remaining);
+                let n = self.reader.read(&mut buf[..len])?;
+                self.binary_remaining -= n;
+                return Ok(n);
+            }
+            self.read_line()?;
+        }
+        let n = (&self.line[self.line_pos..]).read(buf)?;
+        self.line_pos += n;
+        Ok(n)
+    }
+}
+
+impl Write for MpdStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.encoding == TagEncoding::Utf8 {
+            return self.reader.get_mut().write(buf);
+        }
+        // Held until flushed, so that characters split across writes are converted whole.
+        self.command.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.command.is_empty() {
+            let command = String::from_utf8_lossy(&self.command);
+            let (bytes, _, had_errors) = WINDOWS_1252.encode(&command);
+            if had_errors {
+                debug!("lossy conversion of {:?} to {:?}", command, self.encoding);
+            }
+            self.reader.get_mut().write_all(&bytes)?;
+            self.command.clear();
+        }
+        self.reader.get_mut().flush()
+    }
+}
@@ -0,0 +1,299 @@
+//! A minimal HTTP/1.1 client, for fetching album art from web services.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use log::debug;
+
+use crate::Result;
+
+/// How long connecting, and each read and write, may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of redirects followed before giving up.
+const MAX_REDIRECTS: usize = 5;
+
+enum Response {
+    Body(Vec<u8>),
+    Redirect(String),
+}
+
+/// Fetches `url`, which must be `http://`, or `https://` with the `tls` feature, following
+/// redirects, and returns the body if the status is 200. A body larger than `max_bytes` is an
+/// error, and no more of it than that is read.
+pub fn get(url: &str, max_bytes: usize) -> Result<Vec<u8>> {
+    request("GET", url, &[], &[], max_bytes)
+}
+
+/// Like `get`, but with the given method, extra headers and body.
+pub fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    max_bytes: usize,
+) -> Result<Vec<u8>> {
+    let mut url = url.to_owned();
+    for _ in 0..=MAX_REDIRECTS {
+        match request_once(method, &url, headers, body, max_bytes)? {
+            Response::Body(body) => return Ok(body),
+            Response::Redirect(location) => {
+                debug!("redirected to {}", location);
+                url = resolve(&url, &location);
+            }
+        }
+    }
+    Err(format!("too many redirects fetching {}", url).into())
+}
+
+/// `location` from a redirect of `url`, made absolute.
+fn resolve(url: &str, location: &str) -> String {
+    if location.contains("://") {
+        return location.to_owned();
+    }
+    let (scheme, rest) = url.split_once("://").unwrap_or(("http", url));
+    if let Some(authority_relative) = location.strip_prefix("//") {
+        return format!("{}://{}", scheme, authority_relative);
+    }
+    let authority = rest.split('/').next().unwrap_or_default();
+    if location.starts_with('/') {
+        format!("{}://{}{}", scheme, authority, location)
+    } else {
+        // Relative to the directory of the path.
+        let dir = rest.rsplit_once('/').map_or(authority, |(dir, _)| dir);
+        format!("{}://{}/{}", scheme, dir, location)
+    }
+}
+
+/// The host and port of `authority`, with the brackets around an IPv6 address removed, and
+/// `default_port` if it has none.
+fn split_authority(authority: &str, default_port: u16) -> Result<(&str, u16)> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, rest) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("unclosed [ in {:?}", authority))?;
+            let port = match rest {
+                "" => None,
+                _ => Some(
+                    rest.strip_prefix(':')
+                        .ok_or_else(|| format!("invalid authority {:?}", authority))?,
+                ),
+            };
+            (host, port)
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse()?,
+        None => default_port,
+    };
+    Ok((host, port))
+}
+
+/// A connection to a web server, with or without TLS.
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+fn request_once(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    max_bytes: usize,
+) -> Result<Response> {
+    let (https, rest) = match url.split_once("://") {
+        Some(("http", rest)) => (false, rest),
+        Some(("https", rest)) if cfg!(feature = "tls") => (true, rest),
+        Some(("https", _)) => return Err(format!("{} needs the tls feature", url).into()),
+        _ => return Err(format!("unsupported URL {:?}", url).into()),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = split_authority(authority, if https { 443 } else { 80 })?;
+
+    debug!("{} {}", method, url);
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("could not resolve {}", host))?;
+    let tcp = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    tcp.set_read_timeout(Some(TIMEOUT))?;
+    tcp.set_write_timeout(Some(TIMEOUT))?;
+    let mut stream: Box<dyn Stream> = match https {
+        #[cfg(feature = "tls")]
+        true => Box::new(crate::tls::connect(host, tcp)?),
+        _ => Box::new(tcp),
+    };
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}/{}\r\nAccept: */*\r\n\
+         Connection: close\r\n",
+        method,
+        path,
+        authority,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )?;
+    for (name, value) in headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    if !body.is_empty() {
+        write!(stream, "Content-Length: {}\r\n", body.len())?;
+    }
+    stream.write_all(b"\r\n")?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut location = None;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse().ok(),
+                "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+                "location" => location = Some(value.to_owned()),
+                _ => (),
+            }
+        }
+        header.clear();
+    }
+    match (status, location) {
+        ("301" | "302" | "303" | "307" | "308", Some(location)) => {
+            return Ok(Response::Redirect(location))
+        }
+        ("200", _) => (),
+        _ => return Err(format!("{} {} failed: {}", method, url, status_line.trim_end()).into()),
+    }
+
+    let too_large = || format!("{} is larger than the limit of {} bytes", url, max_bytes);
+    let mut body = Vec::new();
+    if chunked {
+        read_chunked(&mut reader, &mut body, max_bytes)?;
+    } else if let Some(len) = content_length {
+        if len > max_bytes as u64 {
+            return Err(too_large().into());
+        }
+        reader.take(len).read_to_end(&mut body)?;
+    } else {
+        // One byte more than the limit, to tell if the body goes past it.
+        reader.take(max_bytes as u64 + 1).read_to_end(&mut body)?;
+        if body.len() > max_bytes {
+            return Err(too_large().into());
+        }
+    }
+    Ok(Response::Body(body))
+}
+
+/// Reads a body sent with chunked transfer encoding into `body`, stopping with an error before
+/// a chunk that would take it past `max_bytes`.
+fn read_chunked(reader: &mut impl BufRead, body: &mut Vec<u8>, max_bytes: usize) -> Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        // The size may be followed by extensions after a semicolon.
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| format!("bad chunk size {:?}", line.trim_end()))?;
+        if size == 0 {
+            return Ok(());
+        }
+        let start = body.len();
+        let end = start
+            .checked_add(size)
+            .filter(|&end| end <= max_bytes)
+            .ok_or_else(|| {
+                format!(
+                    "chunked body is larger than the limit of {} bytes",
+                    max_bytes
+                )
+            })?;
+        body.resize(end, 0);
+        reader.read_exact(&mut body[start..])?;
+        // The CRLF after the chunk.
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+}
+
+/// Percent-encodes `s` for use in a URL's query string.
+pub fn encode_query_value(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_redirects() {
+        let url = "http://example.com/a/b?c";
+        assert_eq!(resolve(url, "https://other.org/x"), "https://other.org/x");
+        assert_eq!(resolve(url, "//other.org/x"), "http://other.org/x");
+        assert_eq!(resolve(url, "/x/y"), "http://example.com/x/y");
+        assert_eq!(resolve(url, "x"), "http://example.com/a/x");
+        assert_eq!(resolve("http://example.com", "x"), "http://example.com/x");
+    }
+
+    #[test]
+    fn split_authority_ports() {
+        assert_eq!(
+            split_authority("example.com", 80).unwrap(),
+            ("example.com", 80)
+        );
+        assert_eq!(
+            split_authority("example.com:8080", 80).unwrap(),
+            ("example.com", 8080)
+        );
+        assert_eq!(split_authority("[::1]", 443).unwrap(), ("::1", 443));
+        assert_eq!(split_authority("[::1]:8080", 443).unwrap(), ("::1", 8080));
+        for authority in ["[::1", "[::1]8080", "example.com:http"] {
+            assert!(split_authority(authority, 80).is_err(), "{}", authority);
+        }
+    }
+
+    #[test]
+    fn read_chunked_skips_extensions() {
+        let mut chunked = &b"5;name=value\r\nHello\r\n6\r\n world\r\n0\r\n\r\n"[..];
+        let mut body = Vec::new();
+        read_chunked(&mut chunked, &mut body, 11).unwrap();
+        assert_eq!(body, b"Hello world");
+    }
+
+    #[test]
+    fn read_chunked_stops_at_limit() {
+        let mut chunked = &b"5\r\nHello\r\n6\r\n world\r\n0\r\n\r\n"[..];
+        let mut body = Vec::new();
+        assert!(read_chunked(&mut chunked, &mut body, 10).is_err());
+        assert_eq!(body, b"Hello");
+
+        // A size that overflows the length of the body so far.
+        let mut huge = &b"1\r\nH\r\nffffffffffffffff\r\n"[..];
+        assert!(read_chunked(&mut huge, &mut Vec::new(), usize::MAX).is_err());
+    }
+}
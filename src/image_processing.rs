@@ -0,0 +1,63 @@
+//! Filters applied to album art after decoding and before conversion to text.
+
+use std::borrow::Cow;
+
+use image::{DynamicImage, GrayImage, Luma, RgbaImage};
+use log::{debug, warn};
+
+/// Sharpens `img` with an unsharp mask. `amount` (0.0–5.0) is used as the blur radius of the
+/// mask, so larger values enhance coarser detail; 0.0 leaves the image unchanged.
+pub fn sharpen(img: DynamicImage, amount: f32) -> DynamicImage {
+    if amount <= 0.0 {
+        return img;
+    }
+    if amount > 2.0 {
+        warn!("sharpening by {} may produce artifacts", amount);
+    }
+    // Differences smaller than the threshold are left alone, to avoid amplifying noise.
+    let threshold = 2;
+    img.unsharpen(amount, threshold)
+}
+
+/// Replaces `img` with the magnitude of its Sobel gradients, so that edges are light and flat
+/// areas are dark, like a line drawing.
+pub fn edge_detect(img: &DynamicImage) -> DynamicImage {
+    let gradients = imageproc::gradients::sobel_gradients(&img.to_luma8());
+    let max = gradients.pixels().map(|p| p.0[0]).max().unwrap_or(0).max(1) as f32;
+    let edges = GrayImage::from_fn(gradients.width(), gradients.height(), |x, y| {
+        Luma([(gradients.get_pixel(x, y).0[0] as f32 / max * 255.0) as u8])
+    });
+    DynamicImage::ImageLuma8(edges)
+}
+
+/// Smooths out noise, such as JPEG compression artifacts, with a median filter of the given
+/// `radius` in pixels. A radius of 0 leaves the image unchanged.
+pub fn reduce_noise(img: DynamicImage, radius: u32) -> DynamicImage {
+    if radius == 0 {
+        return img;
+    }
+    debug!("median filter radius: {}", radius);
+    if radius > 3 {
+        warn!("noise reduction with radius {} will be slow", radius);
+    }
+    DynamicImage::ImageRgb8(imageproc::filter::median_filter(
+        &img.to_rgb8(),
+        radius,
+        radius,
+    ))
+}
+
+/// Keeps only every `rate`th row and column of `img`, a much cheaper way to shrink it than
+/// resampling. A rate of 1 leaves the image unchanged.
+pub fn subsample(img: &DynamicImage, rate: u32) -> Cow<'_, DynamicImage> {
+    if rate <= 1 {
+        return Cow::Borrowed(img);
+    }
+    let rgba = img.to_rgba8();
+    let sampled = RgbaImage::from_fn(
+        rgba.width().div_ceil(rate),
+        rgba.height().div_ceil(rate),
+        |x, y| *rgba.get_pixel(x * rate, y * rate),
+    );
+    Cow::Owned(DynamicImage::ImageRgba8(sampled))
+}
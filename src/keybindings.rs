@@ -0,0 +1,240 @@
+//! Mapping of keys to the actions they trigger. The defaults can be replaced per action in the
+//! `[keybindings]` section of the config file.
+
+use std::collections::HashMap;
+
+use log::warn;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    Next,
+    Prev,
+    TogglePause,
+    SeekBackward,
+    SeekForward,
+    VolumeUp,
+    VolumeDown,
+    ScrollLeft,
+    ScrollDown,
+    ScrollUp,
+    ScrollRight,
+    Rate,
+    TogglePlaylist,
+    ToggleHelp,
+    HideHelp,
+    Export,
+    Reconvert,
+    ToggleDebug,
+}
+
+/// Each action with its name in the config file and its default keys.
+const ACTIONS: &[(Action, &str, &[&str])] = &[
+    (Action::Quit, "quit", &["q"]),
+    (Action::Next, "next", &[">"]),
+    (Action::Prev, "prev", &["<"]),
+    (Action::TogglePause, "toggle_pause", &["space"]),
+    (Action::SeekBackward, "seek_backward", &[","]),
+    (Action::SeekForward, "seek_forward", &["."]),
+    (Action::VolumeUp, "volume_up", &["plus", "="]),
+    (Action::VolumeDown, "volume_down", &["-"]),
+    (Action::ScrollLeft, "scroll_left", &["left", "h"]),
+    (Action::ScrollDown, "scroll_down", &["down", "j"]),
+    (Action::ScrollUp, "scroll_up", &["up", "k"]),
+    (Action::ScrollRight, "scroll_right", &["right", "l"]),
+    (Action::Rate, "rate", &["r"]),
+    (Action::TogglePlaylist, "toggle_playlist", &["p"]),
+    (Action::ToggleHelp, "toggle_help", &["?"]),
+    (Action::HideHelp, "hide_help", &["esc"]),
+    (Action::Export, "export", &["e"]),
+    (Action::Reconvert, "reconvert", &["ctrl+r"]),
+    (Action::ToggleDebug, "toggle_debug", &["d"]),
+];
+
+type Key = (KeyCode, KeyModifiers);
+
+pub struct Keymap {
+    actions: HashMap<Key, Action>,
+    /// The keys bound to each action, as written in the config, for the help overlay.
+    key_names: HashMap<Action, Vec<String>>,
+}
+
+impl Keymap {
+    /// Builds the keymap from the defaults, with the keys of the actions named in `overrides`
+    /// replaced. Keys bound to more than one action trigger the first, with a warning.
+    pub fn new(overrides: &HashMap<String, String>) -> Result<Keymap, String> {
+        if let Some(name) = overrides
+            .keys()
+            .find(|name| !ACTIONS.iter().any(|(_, n, _)| n == name))
+        {
+            return Err(format!("unknown action in [keybindings]: {:?}", name));
+        }
+
+        let mut keymap = Keymap {
+            actions: HashMap::new(),
+            key_names: HashMap::new(),
+        };
+        for (action, name, defaults) in ACTIONS {
+            let key_names: Vec<String> = match overrides.get(*name) {
+                Some(key) => vec![key.clone()],
+                None => defaults.iter().map(|key| key.to_string()).collect(),
+            };
+            for key_name in &key_names {
+                let key = parse_key(key_name)
+                    .map_err(|err| format!("invalid key for {}: {}", name, err))?;
+                match keymap.actions.get(&key) {
+                    Some(other) => warn!(
+                        "{:?} is bound to both {:?} and {:?}; using {:?}",
+                        key_name, other, action, other
+                    ),
+                    None => {
+                        keymap.actions.insert(key, *action);
+                    }
+                }
+            }
+            keymap.key_names.insert(*action, key_names);
+        }
+        Ok(keymap)
+    }
+
+    pub fn action(&self, event: &KeyEvent) -> Option<Action> {
+        self.actions
+            .get(&normalize(event.code, event.modifiers))
+            .copied()
+    }
+
+    /// The keys bound to `action`, e.g. "left/h".
+    pub fn describe(&self, action: Action) -> String {
+        self.key_names
+            .get(&action)
+            .map(|names| names.join("/"))
+            .unwrap_or_default()
+    }
+}
+
+/// Parses a key such as "q", "ctrl+n", "f5" or "shift+tab".
+fn parse_key(s: &str) -> Result<Key, String> {
+    let mut parts: Vec<&str> = s.split('+').collect();
+    let key = parts.pop().unwrap_or_default();
+    let mut modifiers = KeyModifiers::NONE;
+    for part in parts {
+        modifiers |= match part.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return Err(format!("unknown modifier {:?} in {:?}", part, s)),
+        };
+    }
+
+    let mut chars = key.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match key.to_ascii_lowercase().as_str() {
+            "esc" | "escape" => KeyCode::Esc,
+            "enter" | "return" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "space" => KeyCode::Char(' '),
+            "plus" => KeyCode::Char('+'),
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            name => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                Some(n @ 1..=12) => KeyCode::F(n),
+                _ => return Err(format!("unknown key {:?}", s)),
+            },
+        },
+    };
+    Ok(normalize(code, modifiers))
+}
+
+/// Terminals report shifted characters as the character itself, usually but not always with
+/// the shift modifier, so shift is folded into the character.
+fn normalize(code: KeyCode, modifiers: KeyModifiers) -> Key {
+    let modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+    match code {
+        KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => (
+            KeyCode::Char(c.to_ascii_uppercase()),
+            modifiers - KeyModifiers::SHIFT,
+        ),
+        _ => (code, modifiers),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_key_names() {
+        assert_eq!(parse_key("q"), Ok((KeyCode::Char('q'), KeyModifiers::NONE)));
+        assert_eq!(
+            parse_key("Ctrl+r"),
+            Ok((KeyCode::Char('r'), KeyModifiers::CONTROL))
+        );
+        assert_eq!(
+            parse_key("plus"),
+            Ok((KeyCode::Char('+'), KeyModifiers::NONE))
+        );
+        assert_eq!(
+            parse_key("space"),
+            Ok((KeyCode::Char(' '), KeyModifiers::NONE))
+        );
+        assert_eq!(parse_key("F12"), Ok((KeyCode::F(12), KeyModifiers::NONE)));
+        assert_eq!(
+            parse_key("shift+tab"),
+            Ok((KeyCode::Tab, KeyModifiers::SHIFT))
+        );
+    }
+
+    #[test]
+    fn parse_key_rejects_unknown_keys() {
+        for s in ["f0", "f13", "fx", "hyper+q", "+", "ctrl+", "nope"] {
+            assert!(parse_key(s).is_err(), "{} parsed", s);
+        }
+    }
+
+    #[test]
+    fn shift_is_folded_into_characters() {
+        let upper = (KeyCode::Char('A'), KeyModifiers::NONE);
+        assert_eq!(parse_key("shift+a"), Ok(upper));
+        assert_eq!(parse_key("A"), Ok(upper));
+        assert_eq!(normalize(KeyCode::Char('A'), KeyModifiers::SHIFT), upper);
+        assert_eq!(normalize(KeyCode::Char('A'), KeyModifiers::NONE), upper);
+    }
+
+    #[test]
+    fn overrides_replace_defaults() {
+        let overrides = HashMap::from([("next".to_owned(), "n".to_owned())]);
+        let keymap = Keymap::new(&overrides).unwrap();
+        let event = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        assert_eq!(keymap.action(&event('n')), Some(Action::Next));
+        assert_eq!(keymap.action(&event('>')), None);
+        assert_eq!(keymap.describe(Action::Next), "n");
+        assert_eq!(keymap.describe(Action::ScrollLeft), "left/h");
+    }
+
+    #[test]
+    fn duplicate_keys_trigger_the_first_action() {
+        let overrides = HashMap::from([("next".to_owned(), "q".to_owned())]);
+        let keymap = Keymap::new(&overrides).unwrap();
+        let event = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE);
+        assert_eq!(keymap.action(&event), Some(Action::Quit));
+    }
+
+    #[test]
+    fn new_rejects_bad_overrides() {
+        let unknown_action = HashMap::from([("fly".to_owned(), "f".to_owned())]);
+        assert!(Keymap::new(&unknown_action).is_err());
+        let unknown_key = HashMap::from([("quit".to_owned(), "f13".to_owned())]);
+        assert!(Keymap::new(&unknown_key).is_err());
+    }
+}
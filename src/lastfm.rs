@@ -0,0 +1,43 @@
+//! Album art from Last.fm, for --last-fm-art.
+
+use log::{debug, info};
+use mpd::Song;
+
+use crate::{http, song_tag, Result};
+
+const API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+
+/// Fetches the largest image Last.fm has of the album of `song`, looked up by its artist and
+/// album tags with `album.getinfo`. Responses larger than `max_bytes` are an error.
+pub fn fetch_art(api_key: &str, song: &Song, max_bytes: usize) -> Result<Option<Vec<u8>>> {
+    let Some(artist) = song_tag(song, "AlbumArtist").or(song.artist.as_deref()) else {
+        return Ok(None);
+    };
+    let Some(album) = song_tag(song, "Album") else {
+        return Ok(None);
+    };
+    let url = format!(
+        "{}?method=album.getinfo&format=json&api_key={}&artist={}&album={}",
+        API_URL,
+        http::encode_query_value(api_key),
+        http::encode_query_value(artist),
+        http::encode_query_value(album)
+    );
+    let info: serde_json::Value = serde_json::from_slice(&http::get(&url, max_bytes)?)?;
+    if let Some(message) = info["message"].as_str() {
+        return Err(format!("Last.fm error: {}", message).into());
+    }
+    // The images are listed from smallest to largest, with empty URLs for missing sizes.
+    let image_url = info["album"]["image"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|image| image["#text"].as_str())
+        .rfind(|url| !url.is_empty());
+    let Some(image_url) = image_url else {
+        debug!("Last.fm has no image for {} - {}", artist, album);
+        return Ok(None);
+    };
+    info!("fetching album art from {}", image_url);
+    http::get(image_url, max_bytes).map(Some)
+}
@@ -1,46 +1,99 @@
+mod art_source;
+mod config;
+mod discogs;
+mod effects;
+mod encoding;
+mod http;
+mod image_processing;
+mod keybindings;
+mod lastfm;
+mod musicbrainz;
+mod server;
+mod spotify;
+#[cfg(feature = "tls")]
+mod tls;
+mod unicode_utils;
+#[cfg(feature = "visualizer")]
+mod visualizer;
+
 use ansi_to_tui::IntoText;
+use art_source::{fetch_art, fetch_art_with_mpd_art, fetch_mpd_art, ArtSourceKind, ArtSources};
 use clap::Parser;
+use config::Config;
 use core::str::FromStr;
-use image::{io::Reader as ImageReader, DynamicImage};
+use effects::ImageEffect;
+use encoding::{Connection, MpdStream, TagEncoding};
+use image::{
+    codecs::{gif::GifDecoder, jpeg::JpegEncoder, png::PngDecoder},
+    imageops::FilterType,
+    io::Reader as ImageReader,
+    AnimationDecoder, DynamicImage, GrayImage, ImageFormat, Luma, Rgb, RgbImage,
+};
 use img_to_ascii::{
     convert::{self, get_conversion_algorithm, get_converter},
     font::Font,
     image::LumaImage,
 };
+use keybindings::{Action, Keymap};
 use log::{debug, info, warn};
 use mpd::{
-    client::Client as MpdClient, song::Song, status::State as MpdState, status::Status as MpdStatus,
+    search::{Query, Term},
+    song::Song,
+    status::State as MpdState,
+    status::Status as MpdStatus,
 };
 use ratatui::{
     backend::CrosstermBackend,
     buffer::Buffer,
     crossterm::{
-        event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+        cursor::{Hide, MoveTo, SetCursorStyle, Show},
+        event::{
+            self, DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture,
+            Event, KeyCode, KeyEvent, KeyEventKind, MouseEventKind,
+        },
         terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
         ExecutableCommand,
     },
-    layout::{Alignment, Rect},
+    layout::{Alignment, Constraint, Layout, Rect},
     prelude::Backend,
-    style::{Modifier, Style},
+    style::{Color, Modifier, Style},
     symbols::border,
-    text::{Span, Text},
+    text::{Line, Span, Text},
     widgets::{
         block::{Position, Title},
-        Block, Padding, Paragraph, Widget,
+        Block, Clear, HighlightSpacing, LineGauge, List, ListItem, ListState, Padding, Paragraph,
+        StatefulWidget, Widget,
     },
-    Frame, Terminal,
+    Frame, Terminal, TerminalOptions, Viewport,
+};
+use serde::Serialize;
+use socket2::SockRef;
+use spotify::Spotify;
+use std::{
+    collections::HashMap, collections::VecDeque, error::Error, path::Path, thread::JoinHandle,
 };
-use std::{error::Error, path::Path, thread::JoinHandle};
 use std::{
-    io::{stdout, Cursor},
-    net::ToSocketAddrs,
+    io::{stdout, Cursor, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    process::{Child, Command, Stdio},
 };
 use std::{
     path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
     time::{Duration, Instant},
 };
+#[cfg(feature = "tls")]
+use tls::MpdTls;
+use unicode_utils::{normalize, to_fullwidth, NormalizationForm};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+type MpdClient = mpd::Client<MpdStream>;
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
+/// What a fetching thread hands back: the art, and how long it took.
+type Fetched = (Option<Vec<u8>>, Duration);
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -49,12 +102,438 @@ struct Args {
     host: String,
     #[arg(long, value_name = "PORT", default_value_t = 6600)]
     port: u16,
-    #[arg(long, value_name = "LEVEL", default_value = "WARN")]
-    log_level_filter: String,
+    /// Reach MPD through an SSH tunnel to USER@HOST, forwarded to --port on that host
+    #[arg(long, value_name = "USER@HOST", conflicts_with = "host")]
+    ssh_tunnel: Option<String>,
+    /// Size in bytes to request for the MPD socket's receive buffer
+    #[arg(long, value_name = "BYTES", default_value_t = 65536)]
+    socket_buffer_size: usize,
+    /// Encoding of the tags in MPD's database, for servers that don't use UTF-8
+    #[arg(long, value_name = "ENCODING", value_enum, ignore_case = true, default_value_t = TagEncoding::Utf8)]
+    encoding: TagEncoding,
+    /// Connect to MPD over TLS, e.g. through stunnel; its certificate is checked against
+    /// --host, so it can't be used with --ssh-tunnel
+    #[cfg(feature = "tls")]
+    #[arg(long, conflicts_with = "ssh_tunnel")]
+    mpd_tls: bool,
+    /// PEM certificates to trust MPD's certificate by, besides the built-in roots, for --mpd-tls
+    #[cfg(feature = "tls")]
+    #[arg(long, value_name = "PATH", requires = "mpd_tls")]
+    tls_ca: Option<PathBuf>,
+    /// PEM certificate chain to authenticate to MPD with, for --mpd-tls
+    #[cfg(feature = "tls")]
+    #[arg(long, value_name = "PATH", requires_all = ["mpd_tls", "tls_key"])]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of --tls-cert
+    #[cfg(feature = "tls")]
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Connect to MPD over IPv4 only
+    #[arg(long, conflicts_with = "ipv6_only")]
+    ipv4_only: bool,
+    /// Connect to MPD over IPv6 only
+    #[arg(long)]
+    ipv6_only: bool,
+    /// Number of times to try connecting to MPD at startup, a second apart, or 0 to keep
+    /// trying forever
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    connection_retry_on_start: u32,
+    /// How often to check that MPD still accepts connections, reconnecting if it doesn't, or
+    /// 0 to never check
+    #[arg(long, value_name = "SECS", default_value_t = 30.0, value_parser = parse_secs)]
+    connection_check_interval: f64,
+    /// Level of messages to log, e.g. DEBUG; overrides --verbosity [default: WARN]
+    #[arg(long, value_name = "LEVEL")]
+    log_level_filter: Option<String>,
+    /// Shorthand for --log-level-filter: 0 = off, 1 = error, 2 = warn, 3 = info, 4 = debug,
+    /// 5 = trace
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=5))]
+    verbosity: u8,
+    /// Config file to read instead of $XDG_CONFIG_HOME/mpd-album-art-viewer/config.toml
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
     #[arg(long, value_name = "PIXELS", default_value_t = 15.)]
     font_height: f64,
     #[arg(long, value_name = "PIXELS", default_value_t = 8.0)]
     font_width: f64,
+    #[arg(long, value_name = "SECS", default_value_t = 1.0, value_parser = parse_positive_secs)]
+    status_refresh_rate: f64,
+    #[arg(long, value_name = "SECS", default_value_t = 5.0, value_parser = parse_positive_secs)]
+    art_refresh_rate: f64,
+    /// Stop converting new album art after this long without a keypress, or 0 to never stop
+    #[arg(long, value_name = "SECS", default_value_t = 60.0, value_parser = parse_secs)]
+    idle_timeout: f64,
+    /// How long to wait for terminal input on each pass of the event loop; lower values react
+    /// faster at the cost of CPU
+    #[arg(long, value_name = "MILLIS", default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..=100))]
+    event_poll_timeout_ms: u64,
+    /// How long after the first frame is drawn to start fetching album art
+    #[arg(long, value_name = "MILLIS", default_value_t = 0)]
+    startup_fetch_delay: u64,
+    /// Color theme: dark, light, gruvbox, nord, solarized or dracula; colors set in the
+    /// config file's [colors] section or with the color options below take precedence
+    #[arg(long, value_name = "NAME", default_value = "dark", value_parser = parse_theme)]
+    theme: Theme,
+    /// Color of the border, e.g. "#5f87af"
+    #[arg(long, value_name = "COLOR")]
+    border_color: Option<Color>,
+    /// Color of the song and status titles, e.g. "#d7af5f"
+    #[arg(long, value_name = "COLOR")]
+    title_color: Option<Color>,
+    /// Characters to draw the border with, as TL:T:TR:R:BR:B:BL:L, or "double"
+    #[arg(long, value_name = "CHARS", value_parser = parse_border_set)]
+    border_unicode_box: Option<border::Set>,
+    /// Show the song's lyrics in a side panel, scrolling along if they are synced (LRC)
+    #[arg(long)]
+    scroll_lyrics: bool,
+    /// Scroll the song title when it is too long to fit in the border
+    #[arg(long)]
+    title_marquee: bool,
+    /// Maximum number of lines the song title can wrap onto; lines that don't fit in the
+    /// border are stacked above it
+    #[arg(long, value_name = "LINES", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    wrap_title: u16,
+    /// Width of the song title's characters in columns; 2 draws it with fullwidth characters
+    #[arg(long, value_name = "SCALE", default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+    title_scale: u8,
+    /// Number of lines below the album art for a status bar with the song and progress,
+    /// instead of showing them in the border
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    status_bar_height: u16,
+    /// How fast --title-marquee scrolls the title
+    #[arg(
+        long,
+        alias = "marquee-speed",
+        value_name = "CHARS_PER_SEC",
+        default_value_t = 3.0,
+        value_parser = parse_positive_f64
+    )]
+    title_scroll_speed: f64,
+    /// Tag holding the "Artist - Title" of the current track on internet radio streams
+    #[arg(long, value_name = "TAG", default_value = "TITLE")]
+    stream_title_tag: String,
+    /// Show a grid of the album art of the albums in the queue
+    #[arg(long)]
+    playlist_art: bool,
+    #[arg(long, value_name = "N", default_value_t = 9)]
+    playlist_art_max: usize,
+    /// Image to tile as the terminal background, behind the album art
+    #[arg(long, value_name = "PATH")]
+    background_image: Option<PathBuf>,
+    /// Draw album art with Braille characters, at 2x4 pixels per cell
+    #[arg(long)]
+    pixel_art_mode: bool,
+    /// Luminance (0.0–1.0) above which a pixel is drawn as a dot in Braille mode
+    #[arg(long, value_name = "THRESHOLD", default_value_t = 0.5, value_parser = parse_fraction)]
+    braille_threshold: f32,
+    /// Draw album art with half block characters, at 1x2 pixels per cell
+    #[arg(long, conflicts_with = "pixel_art_mode")]
+    half_block_mode: bool,
+    /// Time converting the current song's art with each ASCII converter and algorithm, print
+    /// the results, and exit
+    #[arg(long)]
+    conversion_benchmark: bool,
+    /// Print the current song's album art, sized for the terminal, and exit
+    #[arg(long)]
+    once: bool,
+    /// Format --once prints the art in
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = OutputFormat::Ansi, requires = "once")]
+    output_format: OutputFormat,
+    /// Print a sample of each kind of character the art can be drawn with, and exit
+    #[arg(long)]
+    ascii_charset_preview: bool,
+    /// Largest decoded image, in megabytes, that will be converted
+    #[arg(long, value_name = "MB", default_value_t = 512)]
+    memory_limit: usize,
+    /// Convert only every Nth row and column of pixels, for faster but coarser conversion
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pixel_sample_rate: u32,
+    /// Pixels to crop from each edge of the album art before converting it
+    #[arg(long, value_name = "T:R:B:L", value_parser = parse_crop)]
+    art_crop: Option<Crop>,
+    /// Automatically crop solid-color borders from the album art
+    #[arg(long, overrides_with = "no_auto_crop")]
+    auto_crop: bool,
+    /// Don't crop borders from the album art automatically, even with auto_crop set in the
+    /// config file
+    #[arg(long, overrides_with = "auto_crop")]
+    no_auto_crop: bool,
+    /// How far, per RGB channel, a pixel may be from the border color and still be cropped
+    #[arg(long, value_name = "TOLERANCE", default_value_t = 10)]
+    auto_crop_tolerance: u8,
+    /// Amount (0.0–5.0) to sharpen the album art by before converting it
+    #[arg(long, value_name = "AMOUNT", default_value_t = 0.0, value_parser = |s: &str| parse_f32_between(s, 0.0, 5.0))]
+    sharpen: f32,
+    /// Comma-separated adjustments to apply to the album art in order, after the other
+    /// filters: gamma:G, brightness:N, contrast:PERCENT, saturation:FACTOR, hue:DEGREES,
+    /// sharpen:AMOUNT, blur:SIGMA, grayscale and invert, e.g. "gamma:2.0,saturation:1.5"
+    #[arg(long, value_name = "LIST", value_delimiter = ',', value_parser = effects::parse_effect)]
+    art_effects: Vec<ImageEffect>,
+    /// Scale (0.1–5.0) of the album art relative to the viewport; art larger than the viewport
+    /// can be scrolled with the arrow keys or h/j/k/l
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0, value_parser = |s: &str| parse_f32_between(s, 0.1, 5.0))]
+    zoom: f32,
+    /// Round the width of the converted art down to a multiple of --align-multiple columns,
+    /// for terminals that misalign the border next to some widths
+    #[arg(long)]
+    align_art_pixels: bool,
+    /// Number of columns the width is a multiple of with --align-art-pixels
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..), requires = "align_art_pixels")]
+    align_multiple: u32,
+    /// Radius in pixels (1–5) of the median filter used to reduce noise in the album art, or 0
+    /// to disable it
+    #[arg(long, value_name = "RADIUS", default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=5))]
+    noise_reduction: u32,
+    /// Draw only the edges of the album art, like a line drawing
+    #[arg(long)]
+    edge_detect: bool,
+    /// Invert the colors of the album art
+    #[arg(long)]
+    invert: bool,
+    /// How the art is scaled to the viewport
+    #[arg(long, value_name = "MODE", value_enum, default_value_t = ScaleToFit::Contain)]
+    scale_to_fit: ScaleToFit,
+    /// Play animated GIF and APNG album art instead of showing its first frame
+    #[arg(long)]
+    art_animation: bool,
+    /// Shell command to pipe the album art to, as ANSI text, each time it changes
+    #[arg(long, value_name = "CMD")]
+    output_pipe: Option<String>,
+    /// Shell command to run whenever the playing song changes, with MPD_ARTIST, MPD_TITLE,
+    /// MPD_ALBUM and MPD_FILE set from the new song
+    #[arg(long, value_name = "CMD")]
+    song_change_hook: Option<String>,
+    /// Shell command that writes a song's album art to its standard output, with MPD_FILE set
+    /// to the song's file; its art is used instead of MPD's when both have some. It is killed
+    /// if it runs for more than 30 seconds
+    #[arg(long, value_name = "CMD")]
+    art_fetch_hook: Option<String>,
+    /// Use MPD's album art instead of --art-fetch-hook's when both have some
+    #[arg(long, requires = "art_fetch_hook")]
+    prefer_mpd_art: bool,
+    /// Largest album art in bytes to accept from MPD or a web service; larger art is treated as
+    /// missing
+    #[arg(long, value_name = "N", default_value_t = 20 * 1024 * 1024)]
+    album_art_max_fetch_bytes: usize,
+    /// Look up art on Last.fm by the song's artist and album when MPD and --art-fetch-hook have
+    /// none; needs the tls feature
+    #[arg(long)]
+    last_fm_art: bool,
+    /// Key for Last.fm's API, for --last-fm-art [default: $LASTFM_API_KEY]
+    #[arg(long, value_name = "KEY")]
+    lastfm_api_key: Option<String>,
+    /// Look up art on the Cover Art Archive by the song's MusicBrainz release ID when MPD and
+    /// --art-fetch-hook have none; needs the tls feature
+    #[arg(long)]
+    musicbrainz_art: bool,
+    /// Look up art on Discogs by the song's DISCOGS_RELEASE_ID tag, or else its artist and
+    /// album, when MPD and --art-fetch-hook have none; needs the tls feature
+    #[arg(long, requires = "discogs_token")]
+    discogs_art: bool,
+    /// Personal access token for Discogs's API, for --discogs-art
+    #[arg(long, value_name = "TOKEN")]
+    discogs_token: Option<String>,
+    /// Look up art on Spotify by the song's artist and album when MPD and --art-fetch-hook have
+    /// none; needs the tls feature
+    #[arg(long, requires_all = ["spotify_client_id", "spotify_client_secret"])]
+    spotify_art: bool,
+    /// Client ID of a Spotify app, for --spotify-art
+    #[arg(long, value_name = "ID")]
+    spotify_client_id: Option<String>,
+    /// Client secret of a Spotify app, for --spotify-art
+    #[arg(long, value_name = "SECRET")]
+    spotify_client_secret: Option<String>,
+    /// Use art from the web services of --last-fm-art, --musicbrainz-art, --discogs-art and
+    /// --spotify-art instead of MPD's and --art-fetch-hook's when both have some
+    #[arg(long)]
+    prefer_remote_art: bool,
+    /// Comma-separated album art sources to try, in order; sources left out aren't used, and
+    /// web services still need their own flags [default: from --prefer-mpd-art and
+    /// --prefer-remote-art]
+    #[arg(
+        long,
+        value_name = "LIST",
+        value_delimiter = ',',
+        conflicts_with_all = ["prefer_mpd_art", "prefer_remote_art"]
+    )]
+    art_priority: Option<Vec<ArtSourceKind>>,
+    /// Strip ANSI escape codes from the text sent to --output-pipe and printed by --once
+    #[arg(long)]
+    no_ansi_output: bool,
+    /// Leave the playback state out of the border while MPD is stopped
+    #[arg(long)]
+    hide_status_when_stopped: bool,
+    /// Show the bitrate of the playing song next to the playback state
+    #[arg(long)]
+    status_show_bitrate: bool,
+    /// Keep the art as it is when the terminal is resized, instead of converting it again to
+    /// fit; ctrl+r still converts it again
+    #[arg(long)]
+    no_resize_on_window_change: bool,
+    /// How the volume is shown after changing it with + or -
+    #[arg(long, value_name = "STYLE", value_enum, default_value_t = VolumeDisplayStyle::Both)]
+    volume_display_style: VolumeDisplayStyle,
+    /// Seconds the seek keys and --mouse-scroll-seeks seek by
+    #[arg(long, value_name = "SECS", default_value_t = 5.0, value_parser = parse_positive_secs)]
+    seek_step: f64,
+    /// Seek back and forward in the playing song with the mouse wheel
+    #[arg(long)]
+    mouse_scroll_seeks: bool,
+    /// Format the art is saved in when exporting it with e
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = ExportFormat::Png)]
+    export_format: ExportFormat,
+    /// Quality of art exported as JPEG, from 1 to 100
+    #[arg(long, value_name = "QUALITY", default_value_t = 85, value_parser = clap::value_parser!(u8).range(1..=100))]
+    export_jpeg_quality: u8,
+    /// Show the playing song's position in the queue, e.g. [3/42], next to the playback state
+    #[arg(long)]
+    show_queue_position: bool,
+    /// Show the next song in the queue at the bottom left of the border
+    #[arg(long)]
+    show_next_song: bool,
+    /// File to append a line of JSON to, with timings and sizes, each time new art is shown
+    #[arg(long, value_name = "PATH")]
+    metrics_file: Option<PathBuf>,
+    /// Directory to save a PNG of each newly fetched album art to, named after the time and
+    /// the song
+    #[arg(long, value_name = "DIR")]
+    snapshot_on_change: Option<PathBuf>,
+    /// Number of snapshots to keep in the --snapshot-on-change directory, deleting the oldest
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100,
+        requires = "snapshot_on_change"
+    )]
+    snapshot_max_files: usize,
+    /// Show and edit per-song ratings, stored as MPD stickers (press r to rate)
+    #[arg(long)]
+    enable_stickers: bool,
+    /// Number of trailing directories of a song's path that identify its album, e.g. 2 for
+    /// albums split into disc directories
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    album_dir_depth: u16,
+    /// Show the frame rate in the top-right corner
+    #[arg(long)]
+    fps_counter: bool,
+    /// While art is being converted, draw only every Nth pass of the event loop, leaving more
+    /// CPU time for the conversion
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    frame_skip: u32,
+    /// Character to draw a large diamond of when there is no album art, e.g. ♫, instead of
+    /// saying "No image"
+    #[arg(long, value_name = "CHAR")]
+    art_placeholder: Option<char>,
+    /// Give the colors of the art random hues, keeping their lightness, picked anew for each
+    /// song
+    #[arg(long)]
+    shuffle_art_colors: bool,
+    /// Dim the art while MPD is paused
+    #[arg(long)]
+    dim_on_pause: bool,
+    /// Character to fill the padding between the art and its border with, e.g. · or ░
+    #[arg(long, value_name = "CHAR")]
+    art_border_padding_char: Option<char>,
+    /// Text to show dimmed in the bottom-right corner of the art
+    #[arg(long, value_name = "TEXT")]
+    watermark: Option<String>,
+    /// Color of the --watermark, e.g. "#808080"
+    #[arg(long, value_name = "COLOR", requires = "watermark")]
+    watermark_color: Option<Color>,
+    /// Truncate the song title and status to this many columns, ending them with "…"; 0 for
+    /// no limit
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    max_title_length: usize,
+    /// Unicode normalization form to show tags in, for servers whose tags mix forms
+    #[arg(long, value_name = "FORM", value_enum, ignore_case = true, default_value_t = NormalizationForm::Nfc)]
+    unicode_normalize: NormalizationForm,
+    /// Keep showing the current art instead of fetching art for songs shorter than this many
+    /// seconds, such as interludes and gaps between tracks; 0 never skips
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    skip_short_songs: u64,
+    /// Repeat the album art to fill the whole terminal instead of showing it once
+    #[arg(long)]
+    tiling_mode: bool,
+    /// Fetch and convert the next song's art in the background, so it shows as soon as the
+    /// song changes
+    #[arg(long)]
+    preload_next_art: bool,
+    /// Show the previous album's art on the left, next to the current album's art
+    #[arg(long, conflicts_with = "playlist_art")]
+    compare: bool,
+    /// Highlight the playing song in the playlist view (toggled with p)
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    mark_current_playlist_song: bool,
+    /// Address to serve the current song, status and art on, as JSON at /state, e.g.
+    /// 127.0.0.1:8080
+    #[arg(long, value_name = "ADDR")]
+    listen: Option<SocketAddr>,
+    /// Also accept WebSocket connections to /state on the --listen address, sending the whole
+    /// state, then the state without the art each time it changes and {"image": ...} each time
+    /// the art changes
+    #[arg(long, requires = "listen")]
+    websocket: bool,
+    /// Start playing if MPD is stopped when the viewer starts
+    #[arg(long)]
+    play_on_start: bool,
+    /// Resume playing if MPD is paused when the viewer starts
+    #[arg(long)]
+    resume_on_start: bool,
+    /// If the MPD queue is empty when the viewer starts, fill it with the whole library and
+    /// play it in random order
+    #[arg(long)]
+    play_random_on_empty_queue: bool,
+    /// Fill the queue for --play-random-on-empty-queue with only the songs whose genre, artist
+    /// or album is VALUE
+    #[arg(long, num_args = 2, value_names = ["TAG", "VALUE"], requires = "play_random_on_empty_queue")]
+    play_random_tag: Option<Vec<String>>,
+    /// Stop playback and clear the MPD queue when quitting with q, after confirming
+    #[arg(long)]
+    clear_queue_on_exit: bool,
+    /// Song to add to the MPD queue when the viewer exits, relative to the music directory
+    #[arg(long, value_name = "URI")]
+    queue_on_exit: Option<String>,
+    /// Hide the terminal cursor while running
+    #[arg(long)]
+    hide_cursor: bool,
+    /// Shape of the terminal cursor while running; the terminal's default shape is restored on
+    /// exit
+    #[arg(long, value_name = "STYLE", conflicts_with = "hide_cursor")]
+    cursor_style: Option<CursorStyle>,
+    /// Draw over the normal screen instead of switching to the alternate screen, for
+    /// terminals without one; the terminal's scrollback may be overwritten
+    #[arg(long)]
+    no_alternate_screen: bool,
+    /// Show only a one-line status bar on the last row of the terminal, without art
+    #[arg(long)]
+    compact_mode: bool,
+    /// Don't fetch new album art while the terminal window is unfocused, if the terminal
+    /// reports focus changes
+    #[arg(long)]
+    pause_fetch_when_hidden: bool,
+    /// Show the keybinding help (also toggled with ?) for a few seconds after starting
+    #[arg(long)]
+    help_on_start: bool,
+    /// How long --help-on-start shows the keybinding help
+    #[arg(long, value_name = "SECONDS", default_value_t = 5.0, value_parser = parse_secs)]
+    help_duration_secs: f64,
+    /// How long to show an error in place of the album art, or 0 to show it until a key is
+    /// pressed
+    #[arg(long, value_name = "SECS", default_value_t = 0.0, value_parser = parse_secs)]
+    error_display_duration: f64,
+    /// Directory to write each drawn frame to as a text file, for debugging
+    #[cfg(debug_assertions)]
+    #[arg(long, value_name = "PATH")]
+    frame_dump_dir: Option<PathBuf>,
+    /// Show a live audio spectrum next to the album art, read from an MPD FIFO output
+    #[cfg(feature = "visualizer")]
+    #[arg(long)]
+    visualizer: bool,
+    /// FIFO to read --visualizer's audio from, in MPD's 44100:16:2 format [default:
+    /// ~/.config/mpd/fifo]
+    #[cfg(feature = "visualizer")]
+    #[arg(long, value_name = "PATH", requires = "visualizer")]
+    visualizer_fifo: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
@@ -66,8 +545,10 @@ fn main() -> Result<()> {
             let mut log_path = PathBuf::from(xdg_state_home);
             log_path.push(env!("CARGO_PKG_NAME"));
             log_path.push("log");
-            let log_level_filter: log::LevelFilter =
-                log::LevelFilter::from_str(&args.log_level_filter)?;
+            let log_level_filter = match &args.log_level_filter {
+                Some(level) => log::LevelFilter::from_str(level)?,
+                None => verbosity_to_filter(args.verbosity),
+            };
             match simple_logging::log_to_file(&log_path, log_level_filter) {
                 Ok(()) => Ok(()),
                 Err(err) => Err(format!(
@@ -80,36 +561,401 @@ fn main() -> Result<()> {
         }
     }
 
-    let host_port = format!("{}:{}", args.host, args.port);
-    let mut app = App::create(
-        &host_port,
-        args.font_height.round() as usize,
-        args.font_width.round() as usize,
-    )?;
+    let play_random_tag = match args.play_random_tag.as_deref() {
+        Some([tag, value]) => {
+            let tag = <RandomTag as clap::ValueEnum>::from_str(tag, true).map_err(|_| {
+                format!(
+                    "--play-random-tag must be genre, artist or album, not {:?}",
+                    tag
+                )
+            })?;
+            Some((tag, value.clone()))
+        }
+        _ => None,
+    };
+
+    let lastfm_api_key = match args.last_fm_art {
+        false => None,
+        true => Some(
+            args.lastfm_api_key
+                .clone()
+                .or_else(|| std::env::var("LASTFM_API_KEY").ok())
+                .ok_or("--last-fm-art needs --lastfm-api-key or LASTFM_API_KEY")?,
+        ),
+    };
+    if !cfg!(feature = "tls") {
+        let needs_tls = [
+            ("--musicbrainz-art", args.musicbrainz_art),
+            ("--discogs-art", args.discogs_art),
+            ("--spotify-art", args.spotify_art),
+            ("--last-fm-art", args.last_fm_art),
+        ];
+        if let Some((flag, _)) = needs_tls.iter().find(|(_, set)| *set) {
+            return Err(format!("{} needs the tls feature", flag).into());
+        }
+    }
+
+    let config = Config::load(args.config.as_deref())?;
+    let keymap = Keymap::new(&config.keybindings)?;
+
+    if !args.export_format.image_format().writing_enabled() {
+        return Err(format!(
+            "--export-format: writing {:?} is not enabled in this build's image crate",
+            args.export_format.image_format()
+        )
+        .into());
+    }
+
+    let mut theme = args.theme;
+    theme.apply_colors(&config.colors)?;
+    if args.border_color.is_some() {
+        theme.border_color = args.border_color;
+    }
+    if args.title_color.is_some() {
+        theme.title_color = args.title_color;
+    }
+    if let Some(border_set) = args.border_unicode_box {
+        theme.border_set = border_set;
+    }
+
+    #[cfg(debug_assertions)]
+    if let Some(dir) = &args.frame_dump_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let conversion = ConversionOptions {
+        mode: if args.pixel_art_mode {
+            ConversionMode::Braille
+        } else if args.half_block_mode {
+            ConversionMode::HalfBlock
+        } else {
+            ConversionMode::Ascii
+        },
+        braille_threshold: args.braille_threshold,
+        memory_limit: args.memory_limit.saturating_mul(1024 * 1024),
+        pixel_sample_rate: args.pixel_sample_rate,
+        crop: args.art_crop,
+        auto_crop_tolerance: (args.auto_crop || (config.auto_crop && !args.no_auto_crop))
+            .then_some(args.auto_crop_tolerance),
+        sharpen: args.sharpen,
+        effects: args.art_effects,
+        zoom: args.zoom as f64,
+        align_multiple: args
+            .align_art_pixels
+            .then_some(args.align_multiple as usize),
+        noise_reduction: args.noise_reduction,
+        edge_detect: args.edge_detect,
+        invert: args.invert,
+        animation: args.art_animation,
+        scale_to_fit: args.scale_to_fit,
+    };
+    if args.ascii_charset_preview {
+        print_charset_preview(
+            App::load_font(
+                args.font_height.round() as usize,
+                args.font_width.round() as usize,
+            ),
+            conversion,
+        );
+        return Ok(());
+    }
+
+    #[cfg(feature = "tls")]
+    let mpd_tls = match args.mpd_tls {
+        true => Some(MpdTls::new(
+            &args.host,
+            args.tls_ca.as_deref(),
+            args.tls_cert.as_deref(),
+            args.tls_key.as_deref(),
+        )?),
+        false => None,
+    };
+
+    let (host, port, ssh_tunnel) = match &args.ssh_tunnel {
+        Some(user_host) => {
+            let (child, local_port) = setup_ssh_tunnel(user_host, args.port)?;
+            ("127.0.0.1".to_string(), local_port, Some(SshTunnel(child)))
+        }
+        None => (args.host, args.port, None),
+    };
+
+    let display = DisplayOptions {
+        scroll_lyrics: args.scroll_lyrics,
+        marquee_speed: args.title_marquee.then_some(args.title_scroll_speed),
+        title_lines: args.wrap_title as usize,
+        title_scale: args.title_scale as usize,
+        status_bar_height: args.status_bar_height,
+        stream_title_tag: args.stream_title_tag,
+        playlist_art_max: args.playlist_art.then_some(args.playlist_art_max),
+        background_image: args.background_image,
+        output_pipe: args.output_pipe,
+        song_change_hook: args.song_change_hook,
+        art_fetch_hook: args.art_fetch_hook,
+        art_priority: args.art_priority.unwrap_or_else(|| {
+            ArtSourceKind::default_priority(args.prefer_mpd_art, args.prefer_remote_art)
+        }),
+        album_art_max_fetch_bytes: args.album_art_max_fetch_bytes,
+        lastfm_api_key,
+        musicbrainz_art: args.musicbrainz_art,
+        discogs_token: args.discogs_art.then_some(args.discogs_token).flatten(),
+        spotify: match (
+            args.spotify_art,
+            args.spotify_client_id,
+            args.spotify_client_secret,
+        ) {
+            (true, Some(id), Some(secret)) => Some(Arc::new(Spotify::new(id, secret))),
+            _ => None,
+        },
+        metrics_file: args.metrics_file,
+        snapshot_dir: args.snapshot_on_change,
+        snapshot_max_files: args.snapshot_max_files,
+        show_queue_position: args.show_queue_position,
+        show_next_song: args.show_next_song,
+        art_placeholder: args.art_placeholder,
+        shuffle_art_colors: args.shuffle_art_colors,
+        dim_on_pause: args.dim_on_pause,
+        art_border_padding_char: args.art_border_padding_char,
+        watermark: args.watermark,
+        watermark_color: args.watermark_color,
+        max_title_length: (args.max_title_length > 0).then_some(args.max_title_length),
+        unicode_normalize: args.unicode_normalize,
+        skip_short_songs: (args.skip_short_songs > 0)
+            .then(|| Duration::from_secs(args.skip_short_songs)),
+        status_show_bitrate: args.status_show_bitrate,
+        hide_status_when_stopped: args.hide_status_when_stopped,
+        volume_display_style: args.volume_display_style,
+        compact_mode: args.compact_mode,
+        seek_step: Duration::from_secs_f64(args.seek_step),
+        mouse_scroll_seeks: args.mouse_scroll_seeks,
+        clear_queue_on_exit: args.clear_queue_on_exit,
+        export_format: args.export_format,
+        export_jpeg_quality: args.export_jpeg_quality,
+        resize_on_window_change: !args.no_resize_on_window_change,
+        pause_fetch_when_hidden: args.pause_fetch_when_hidden,
+        no_ansi_output: args.no_ansi_output,
+        enable_stickers: args.enable_stickers,
+        album_dir_depth: args.album_dir_depth as usize,
+        fps_counter: args.fps_counter,
+        frame_skip: args.frame_skip,
+        tiling_mode: args.tiling_mode,
+        preload_next_art: args.preload_next_art,
+        compare: args.compare,
+        mark_current_playlist_song: args.mark_current_playlist_song,
+        help_duration: args
+            .help_on_start
+            .then(|| Duration::from_secs_f64(args.help_duration_secs)),
+        error_display_duration: (args.error_display_duration > 0.0)
+            .then(|| Duration::from_secs_f64(args.error_display_duration)),
+        #[cfg(debug_assertions)]
+        frame_dump_dir: args.frame_dump_dir,
+        #[cfg(feature = "visualizer")]
+        visualizer_fifo: args.visualizer.then(|| {
+            args.visualizer_fifo.unwrap_or_else(|| {
+                PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".config/mpd/fifo")
+            })
+        }),
+    };
+    let builder = App::builder()
+        .host(host)
+        .port(port)
+        .ssh_tunnel(ssh_tunnel)
+        .recv_buffer_size(args.socket_buffer_size)
+        .encoding(args.encoding)
+        .connect_attempts(args.connection_retry_on_start)
+        .ip_family(if args.ipv4_only {
+            Some(IpFamily::V4)
+        } else if args.ipv6_only {
+            Some(IpFamily::V6)
+        } else {
+            None
+        })
+        .font_height(args.font_height.round() as usize)
+        .font_width(args.font_width.round() as usize)
+        .update_period(Duration::from_secs_f64(args.status_refresh_rate))
+        .art_refresh_period(Duration::from_secs_f64(args.art_refresh_rate))
+        .idle_timeout((args.idle_timeout > 0.0).then(|| Duration::from_secs_f64(args.idle_timeout)))
+        .event_poll_timeout(Duration::from_millis(args.event_poll_timeout_ms))
+        .startup_fetch_delay(Duration::from_millis(args.startup_fetch_delay))
+        .connection_check_period(
+            (args.connection_check_interval > 0.0)
+                .then(|| Duration::from_secs_f64(args.connection_check_interval)),
+        )
+        .theme(theme)
+        .display(display)
+        .conversion(conversion)
+        .keymap(keymap)
+        .play_on_start(args.play_on_start)
+        .resume_on_start(args.resume_on_start)
+        .play_random_on_empty_queue(args.play_random_on_empty_queue, play_random_tag)
+        .listen(args.listen)
+        .websocket(args.websocket);
+    #[cfg(feature = "tls")]
+    let builder = builder.mpd_tls(mpd_tls);
+    let mut app = builder.build()?;
+
+    if args.conversion_benchmark {
+        return app.conversion_benchmark();
+    }
+    if args.once {
+        return app.print_once(args.output_format);
+    }
+
+    let hide_cursor = args.hide_cursor || args.cursor_style == Some(CursorStyle::Hidden);
+    let cursor_style = args.cursor_style.and_then(CursorStyle::command);
+    let modes = TerminalModes {
+        alternate_screen: !args.no_alternate_screen && !args.compact_mode,
+        cursor_style: cursor_style.is_some(),
+        hide_cursor,
+        focus_change: args.pause_fetch_when_hidden,
+        mouse_capture: args.mouse_scroll_seeks,
+    };
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        modes.restore();
+        default_hook(info);
+    }));
 
     enable_raw_mode()?;
-    stdout().execute(EnterAlternateScreen)?;
-    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    let guard = TerminalGuard(modes);
+    if modes.alternate_screen {
+        stdout().execute(EnterAlternateScreen)?;
+    }
+    if let Some(style) = cursor_style {
+        stdout().execute(style)?;
+    }
+    if modes.hide_cursor {
+        stdout().execute(Hide)?;
+    }
+    if modes.focus_change {
+        stdout().execute(EnableFocusChange)?;
+    }
+    if modes.mouse_capture {
+        stdout().execute(EnableMouseCapture)?;
+    }
+    let mut terminal = if args.compact_mode {
+        // The bar is drawn in place on the last row, leaving the rest of the screen as it was.
+        let (_, rows) = ratatui::crossterm::terminal::size()?;
+        stdout().execute(MoveTo(0, rows.saturating_sub(1)))?;
+        Terminal::with_options(
+            CrosstermBackend::new(stdout()),
+            TerminalOptions {
+                viewport: Viewport::Inline(1),
+            },
+        )?
+    } else {
+        Terminal::new(CrosstermBackend::new(stdout()))?
+    };
+    if args.no_alternate_screen && !args.compact_mode {
+        terminal.clear()?;
+    }
 
     let result = app.run(&mut terminal);
 
-    disable_raw_mode()?;
-    stdout().execute(LeaveAlternateScreen)?;
-    result
+    drop(guard);
+    if args.compact_mode {
+        // Keep the shell's prompt from being drawn over the bar.
+        println!();
+    }
+    result?;
+
+    if let Some(uri) = args.queue_on_exit {
+        app.enqueue(uri)?;
+    }
+    Ok(())
+}
+
+/// Terminal modes that are changed while the UI is shown.
+#[derive(Clone, Copy)]
+struct TerminalModes {
+    alternate_screen: bool,
+    cursor_style: bool,
+    hide_cursor: bool,
+    focus_change: bool,
+    mouse_capture: bool,
+}
+
+impl TerminalModes {
+    /// Puts the terminal back the way it was, ignoring errors so that as much as possible is
+    /// restored.
+    fn restore(&self) {
+        // Terminals can't report the cursor's shape, so it goes back to the user's default
+        // rather than whatever it was before.
+        if self.cursor_style {
+            let _ = stdout().execute(SetCursorStyle::DefaultUserShape);
+        }
+        if self.hide_cursor {
+            let _ = stdout().execute(Show);
+        }
+        if self.focus_change {
+            let _ = stdout().execute(DisableFocusChange);
+        }
+        if self.mouse_capture {
+            let _ = stdout().execute(DisableMouseCapture);
+        }
+        let _ = disable_raw_mode();
+        if self.alternate_screen {
+            let _ = stdout().execute(LeaveAlternateScreen);
+        }
+    }
+}
+
+/// Restores the terminal's modes when dropped, including when returning early with an error.
+struct TerminalGuard(TerminalModes);
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        self.0.restore();
+    }
+}
+
+/// Art converted ahead of time, with the bytes and context it was converted from.
+struct ConvertedArt {
+    img: DynamicImage,
+    text: Text<'static>,
+    bytes: Vec<u8>,
+    context: ConversionContext,
 }
 
 enum ImgState {
     Idle(Option<(DynamicImage, Text<'static>)>),
-    Fetching(JoinHandle<(MpdClient, Option<Vec<u8>>)>),
-    Converting(JoinHandle<Option<(DynamicImage, Text<'static>)>>),
+    /// Animated art, with its first frame.
+    Animated(DynamicImage, Animation),
+    /// Fetching art, which returns the art and how long fetching took.
+    Fetching(JoinHandle<Fetched>),
+    /// Converting art, which returns the new state and how long converting took.
+    Converting(JoinHandle<std::result::Result<(ImgState, Duration), String>>),
+    /// Conversion was refused, with a message to show in place of the art.
+    Error(String),
 }
 
 impl std::fmt::Debug for ImgState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Idle(_) => f.debug_tuple("Idle").finish(),
+            Self::Animated(..) => f.debug_tuple("Animated").finish(),
             Self::Fetching(_) => f.debug_tuple("Fetching").finish(),
             Self::Converting(_) => f.debug_tuple("Converting").finish(),
+            Self::Error(msg) => f.debug_tuple("Error").field(msg).finish(),
+        }
+    }
+}
+
+impl std::fmt::Display for ImgState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Idle(Some((img, _))) => write!(f, "Idle({}x{})", img.width(), img.height()),
+            Self::Idle(None) => write!(f, "Idle(no image)"),
+            Self::Animated(img, animation) => write!(
+                f,
+                "Animated({}x{}, frame {} of {})",
+                img.width(),
+                img.height(),
+                animation.current_frame_index + 1,
+                animation.frames.len()
+            ),
+            Self::Fetching(jh) => write!(f, "Fetching(finished: {})", jh.is_finished()),
+            Self::Converting(jh) => write!(f, "Converting(finished: {})", jh.is_finished()),
+            Self::Error(msg) => write!(f, "Error({})", msg),
         }
     }
 }
@@ -119,6 +965,10 @@ impl ImgState {
         matches!(self, ImgState::Fetching(_))
     }
 
+    fn is_error(&self) -> bool {
+        matches!(self, ImgState::Error(_))
+    }
+
     fn is_converting(&self) -> bool {
         matches!(self, ImgState::Converting(_))
     }
@@ -128,25 +978,35 @@ impl ImgState {
         *self = ImgState::Idle(st)
     }
 
-    fn start_fetching(&mut self, mut client: MpdClient, song: Option<Song>) {
+    /// Starts fetching the art of `song` on another thread, which sends `client` back on the
+    /// returned channel as soon as it has fetched MPD's art, before trying the other sources.
+    fn start_fetching(
+        &mut self,
+        mut client: MpdClient,
+        song: Option<Song>,
+        sources: ArtSources,
+    ) -> Receiver<MpdClient> {
         info!("starting fetching of {:?}", song);
-        let jh = std::thread::spawn(move || -> (MpdClient, Option<Vec<u8>>) {
+        let (client_tx, client_rx) = mpsc::channel();
+        let jh = std::thread::spawn(move || -> Fetched {
             let start_album_art = Instant::now();
-            let art: Option<Vec<u8>> = song.as_ref().and_then(|song| -> Option<Vec<u8>> {
-                client
-                    .albumart(song)
-                    .inspect_err(|err| {
-                        warn!("error fetching album art for \"{}\": {:?}", song.file, err)
-                    })
-                    .ok()
-            });
-            info!("fetching album art took {:?}", start_album_art.elapsed());
-            (client, art)
+            let mpd_art = song
+                .as_ref()
+                .and_then(|song| fetch_mpd_art(&mut client, song, &sources));
+            let _ = client_tx.send(client);
+            let art = song
+                .as_ref()
+                .and_then(|song| fetch_art_with_mpd_art(song, &sources, mpd_art));
+            let elapsed = start_album_art.elapsed();
+            info!("fetching album art took {:?}", elapsed);
+            (art, elapsed)
         });
         *self = ImgState::Fetching(jh);
+        client_rx
     }
 
-    fn try_finish_fetching(&mut self) -> Option<(MpdClient, Option<Vec<u8>>)> {
+    /// The fetching thread's result once it's done, or the error it panicked with.
+    fn try_finish_fetching(&mut self) -> Option<std::thread::Result<Fetched>> {
         match self {
             ImgState::Fetching(jh) if jh.is_finished() => (),
             _ => return None,
@@ -157,79 +1017,29 @@ impl ImgState {
         std::mem::swap(&mut tmp, self);
 
         match tmp {
-            ImgState::Fetching(jh) if jh.is_finished() => {
-                let (client, bytes) = jh.join().expect("why would it not join");
-                Some((client, bytes))
-            }
+            ImgState::Fetching(jh) if jh.is_finished() => Some(jh.join()),
             _ => unreachable!(),
         }
     }
 
     fn start_converting(&mut self, bytes: Vec<u8>, conv_ctx: ConversionContext) {
         info!("starting converting");
-        let jh = std::thread::spawn(move || -> Option<(DynamicImage, Text<'static>)> {
-            let dyn_img = ImageReader::new(Cursor::new(bytes))
-                .with_guessed_format()
-                .inspect_err(|err| warn!("error guessing image format: {:?}", err))
-                .ok()?
-                .decode()
-                .inspect_err(|err| warn!("error decoding image: {:?}", err))
-                .ok()?;
-            let viewable_width = conv_ctx.area.width as usize
-                - (HORIZ_VIEWPORT_GAP + HORIZ_BORDER_WIDTH + HORIZ_PADDING) * 2;
-            let viewable_height = conv_ctx.area.height as usize
-                - (VERT_VIEWPORT_GAP + VERT_BORDER_WIDTH + VERT_PADDING) * 2;
-            let viewport_aspect =
-                viewable_width as f64 * conv_ctx.font_aspect / viewable_height as f64;
-            let image_aspect = dyn_img.width() as f64 / dyn_img.height() as f64;
-            info!("viewport: {}; aspect: {}", conv_ctx.area, viewport_aspect);
-            info!(
-                "image: {} x {}; aspect: {}",
-                dyn_img.width(),
-                dyn_img.height(),
-                image_aspect
-            );
-            let width = if image_aspect > viewport_aspect {
-                // Image is wide compared to the viewport, so width will be the determining
-                // factor when scaling.
-                conv_ctx.area.width as usize
-                    - (HORIZ_VIEWPORT_GAP + HORIZ_BORDER_WIDTH + HORIZ_PADDING) * 2
-            } else {
-                // Image is tall compared to the viewport, so height will be the determining
-                // factor when scaling.
-                //
-                // (VERT_VIEWPORT_GAP + VERT_BORDER_WIDTH + VERT_PADDING) * 2 + ascii_img_width * font_aspect / img_aspect ==
-                //   viewport_height
-                //
-                // ascii_img_height == ascii_img_width * font_aspect / img_aspect
-                // Solving for width:
-                //
-                // width = (viewport_height - ((VERT_VIEWPORT_GAP + VERT_BORDER_WIDTH + VERT_PADDING) * 2)) / font_aspect;
-                ((conv_ctx.area.height as usize
-                    - ((VERT_VIEWPORT_GAP + VERT_BORDER_WIDTH + VERT_PADDING) * 2))
-                    as f64
-                    * image_aspect
-                    / conv_ctx.font_aspect) as usize
-            };
-            info!("scaled ascii image width: {}", width);
-            let rows = convert::img_to_char_rows(
-                &conv_ctx.font,
-                &LumaImage::from(&dyn_img),
-                get_converter("direction-and-intensity"),
-                Some(width),
-                0.0,
-                &get_conversion_algorithm("edge-augmented"),
-            );
-            let text = convert::char_rows_to_terminal_color_string(&rows, &dyn_img)
-                .into_text()
-                .inspect_err(|err| warn!("error converting ANSI to `Text`: {:?}", err))
-                .ok()?;
-            Some((dyn_img, text))
+        let jh = std::thread::spawn(move || {
+            let start = Instant::now();
+            conv_ctx.check_memory(&bytes)?;
+            if conv_ctx.options.animation {
+                if let Some((img, animation)) = conv_ctx.convert_animation(&bytes)? {
+                    return Ok((ImgState::Animated(img, animation), start.elapsed()));
+                }
+            }
+            Ok((ImgState::Idle(conv_ctx.convert(bytes)), start.elapsed()))
         });
         *self = ImgState::Converting(jh)
     }
 
-    fn try_finish_converting(&mut self) -> Option<(DynamicImage, Text<'static>)> {
+    /// Replaces the state with the converted art if conversion has finished successfully, and
+    /// returns how long it took.
+    fn try_finish_converting(&mut self) -> Option<Duration> {
         match self {
             ImgState::Converting(jh) if jh.is_finished() => (),
             _ => return None,
@@ -247,9 +1057,93 @@ impl ImgState {
             _ => unreachable!(),
         };
 
-        jh.join()
+        match jh
+            .join()
             .inspect_err(|err| warn!("error joining converting thread: {:?}", err))
-            .ok()?
+        {
+            Ok(Ok((converted, elapsed))) => {
+                *self = converted;
+                Some(elapsed)
+            }
+            Ok(Err(msg)) => {
+                warn!("not converting album art: {}", msg);
+                *self = ImgState::Error(msg);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// The decoded art being shown, if any.
+    fn image(&self) -> Option<&DynamicImage> {
+        match self {
+            ImgState::Idle(Some((img, _))) | ImgState::Animated(img, _) => Some(img),
+            _ => None,
+        }
+    }
+
+    /// The text of the art being shown, if any.
+    fn text(&self) -> Option<&Text<'static>> {
+        match self {
+            ImgState::Idle(Some((_, text))) => Some(text),
+            ImgState::Animated(_, animation) => Some(animation.current_frame()),
+            _ => None,
+        }
+    }
+
+    /// Moves animated art on to its next frame if it's time, and returns whether it moved.
+    fn advance_animation(&mut self) -> bool {
+        match self {
+            ImgState::Animated(_, animation) => animation.advance(),
+            _ => false,
+        }
+    }
+}
+
+/// The converted frames of animated art, and the one being shown.
+struct Animation {
+    frames: Vec<Text<'static>>,
+    delays: Vec<Duration>,
+    current_frame_index: usize,
+    frame_shown_at: Instant,
+}
+
+impl Animation {
+    /// Browsers show frames with shorter delays than this for `DEFAULT_DELAY` instead, and
+    /// some GIFs rely on it.
+    const MIN_DELAY: Duration = Duration::from_millis(20);
+    const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+    fn new(frames: Vec<Text<'static>>, delays: Vec<Duration>) -> Animation {
+        let delays = delays
+            .into_iter()
+            .map(|delay| {
+                if delay < Self::MIN_DELAY {
+                    Self::DEFAULT_DELAY
+                } else {
+                    delay
+                }
+            })
+            .collect();
+        Animation {
+            frames,
+            delays,
+            current_frame_index: 0,
+            frame_shown_at: Instant::now(),
+        }
+    }
+
+    fn current_frame(&self) -> &Text<'static> {
+        &self.frames[self.current_frame_index]
+    }
+
+    fn advance(&mut self) -> bool {
+        if self.frame_shown_at.elapsed() < self.delays[self.current_frame_index] {
+            return false;
+        }
+        self.current_frame_index = (self.current_frame_index + 1) % self.frames.len();
+        self.frame_shown_at = Instant::now();
+        true
     }
 }
 
@@ -259,303 +1153,4674 @@ impl Default for ImgState {
     }
 }
 
-struct ConversionContext {
-    area: Rect,
-    font: Font,
-    font_aspect: f64,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConversionMode {
+    /// ASCII characters chosen by `img_to_ascii`.
+    Ascii,
+    /// Braille characters, each showing a 2x4 block of pixels.
+    Braille,
+    /// Upper half block characters, each showing two pixels as its foreground and background
+    /// colors.
+    HalfBlock,
 }
 
-#[derive(Default)]
-struct State {
-    viewport_area: Rect,
-    current_song: Option<Song>,
-    mpd_status: MpdStatus,
-    img_state: ImgState,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum ScaleToFit {
+    /// As large as fits entirely within the viewport
+    Contain,
+    /// As small as fills the viewport, with the excess cut off
+    Cover,
+    /// As wide as the viewport
+    Width,
+    /// As tall as the viewport
+    Height,
 }
 
-struct App {
-    client: Option<MpdClient>,
-    font: Font,
-    font_aspect: f64,
-    state: State,
-    last_update_time: Option<Instant>,
-    exit: bool,
+#[derive(Clone, Debug, PartialEq)]
+struct ConversionOptions {
+    mode: ConversionMode,
+    braille_threshold: f32,
+    /// Largest estimated size in bytes of a decoded image that will be converted.
+    memory_limit: usize,
+    /// Only every Nth row and column of pixels is converted by the ASCII conversion.
+    pixel_sample_rate: u32,
+    crop: Option<Crop>,
+    /// Color tolerance for cropping borders, if automatic cropping is enabled.
+    auto_crop_tolerance: Option<u8>,
+    sharpen: f32,
+    /// The --art-effects, in the order they're applied.
+    effects: Vec<ImageEffect>,
+    zoom: f64,
+    /// What the width of the converted art is rounded down to a multiple of, if anything.
+    align_multiple: Option<usize>,
+    /// Median filter radius, or 0 for no noise reduction.
+    noise_reduction: u32,
+    edge_detect: bool,
+    invert: bool,
+    /// Whether all frames of animated art are converted, to be played in turn.
+    animation: bool,
+    scale_to_fit: ScaleToFit,
 }
 
-const VERT_VIEWPORT_GAP: usize = 3;
-const VERT_BORDER_WIDTH: usize = 1;
-const VERT_PADDING: usize = 1;
-
-const HORIZ_VIEWPORT_GAP: usize = 6;
-const HORIZ_BORDER_WIDTH: usize = 1;
-const HORIZ_PADDING: usize = 2;
-
-impl App {
-    const UPDATE_PERIOD: Duration = Duration::from_secs(1);
-    const ALPHABET: &'static str = include_str!("../alphabets/alphabet.txt");
-    const BDF_FILE: &'static str = include_str!("../fonts/bitocra-13.bdf");
-
-    pub fn create(host_port: &str, font_height: usize, font_width: usize) -> Result<Self> {
-        let mut addrs_iter = host_port.to_socket_addrs()?;
-        let addr = match addrs_iter.next() {
-            None => return Err("could not resolve host".into()),
-            Some(addr) => addr,
-        };
-
-        let client = Some(MpdClient::connect(addr)?);
-        let alphabet = Self::ALPHABET.chars().collect::<Vec<char>>();
-        let mut font = Font::from_bdf_stream(Self::BDF_FILE.as_bytes(), &alphabet);
-        font.height = font_height;
-        font.width = font_width;
-
-        let font_aspect = font.width as f64 / font.height as f64;
-        info!(
-            "font has width {} and height {}; aspect: {}",
-            font.width, font.height, font_aspect
-        );
-        Ok(App {
-            font,
-            font_aspect,
-            client,
-            state: State::default(),
-            last_update_time: None,
-            exit: false,
-        })
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        ConversionOptions {
+            mode: ConversionMode::Ascii,
+            braille_threshold: 0.5,
+            memory_limit: 512 * 1024 * 1024,
+            pixel_sample_rate: 1,
+            crop: None,
+            auto_crop_tolerance: None,
+            sharpen: 0.0,
+            effects: Vec::new(),
+            zoom: 1.0,
+            align_multiple: None,
+            noise_reduction: 0,
+            edge_detect: false,
+            invert: false,
+            animation: false,
+            scale_to_fit: ScaleToFit::Contain,
+        }
     }
+}
 
-    pub fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
-        self.state.viewport_area = terminal.get_frame().size();
+/// Margins, in pixels, to remove from the edges of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Crop {
+    top: u32,
+    right: u32,
+    bottom: u32,
+    left: u32,
+}
 
-        self.update_app_state()?;
-        terminal.draw(|frame| self.render_frame(frame))?;
-        while !self.exit {
-            self.handle_events()?;
-            terminal.draw(|frame| self.render_frame(frame))?;
+impl Crop {
+    /// Crops `img`, or leaves it alone if the margins would leave nothing.
+    fn apply(&self, img: DynamicImage) -> DynamicImage {
+        let (w, h) = (img.width(), img.height());
+        if self.left.saturating_add(self.right) >= w || self.top.saturating_add(self.bottom) >= h {
+            warn!(
+                "crop {:?} exceeds image size {} x {}; not cropping",
+                self, w, h
+            );
+            return img;
         }
-        Ok(())
+        let cropped = img.crop_imm(
+            self.left,
+            self.top,
+            w - self.left - self.right,
+            h - self.top - self.bottom,
+        );
+        debug!("cropped image: {} x {}", cropped.width(), cropped.height());
+        cropped
     }
+}
 
-    fn render_frame(&self, frame: &mut Frame) {
-        frame.render_widget(self, frame.size())
+#[derive(Clone)]
+struct ConversionContext {
+    area: Rect,
+    font: Font,
+    font_aspect: f64,
+    options: ConversionOptions,
+    /// Whether the art is drawn in a playlist tile, which has a border but no viewport gap
+    /// or padding around it.
+    tile: bool,
+}
+
+/// Fonts are compared by their cell size, since the glyphs are the same for the whole run.
+impl PartialEq for ConversionContext {
+    fn eq(&self, other: &Self) -> bool {
+        self.area == other.area
+            && (self.font.width, self.font.height) == (other.font.width, other.font.height)
+            && self.font_aspect == other.font_aspect
+            && self.options == other.options
+            && self.tile == other.tile
     }
+}
 
-    fn handle_events(&mut self) -> Result<()> {
-        loop {
-            if event::poll(Duration::from_millis(5))? {
-                match event::read()? {
-                    // it's important to check that the event is a key press event as
-                    // crossterm also emits key release and repeat events on Windows.
-                    Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
-                        self.handle_key_event(key_event);
-                        break;
-                    }
-                    _ => {}
-                };
-            }
-            if self.elapsed_since_update() >= Self::UPDATE_PERIOD {
-                self.update_app_state()?;
-                break;
-            }
+impl ConversionContext {
+    /// Refuses to convert images whose decoded size would exceed the memory limit.
+    fn check_memory(&self, bytes: &[u8]) -> std::result::Result<(), String> {
+        let dimensions = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .ok()
+            .and_then(|reader| reader.into_dimensions().ok());
+        match dimensions {
+            Some((w, h)) => self.check_frames_memory(w, h, 1),
+            // If the dimensions can't be read, decoding will fail and be reported later.
+            None => Ok(()),
         }
-        Ok(())
     }
 
-    fn handle_key_event(&mut self, key_event: KeyEvent) {
-        if let KeyCode::Char('q') = key_event.code {
-            self.exit();
+    /// Refuses to decode `frames` frames of `w` x `h` pixels if together they would exceed the
+    /// memory limit.
+    fn check_frames_memory(
+        &self,
+        w: u32,
+        h: u32,
+        frames: usize,
+    ) -> std::result::Result<(), String> {
+        if estimate_decode_memory(w, h).saturating_mul(frames) > self.options.memory_limit {
+            Err("Image too large for memory limit".to_owned())
+        } else {
+            Ok(())
         }
     }
 
-    fn songs_in_same_dir(song0: &Song, song1: &Song) -> bool {
-        let dir0 = Path::new(&song0.file).parent();
-        let dir1 = Path::new(&song1.file).parent();
-        debug!("songs_in_same_dir: {:?}, {:?}", dir0, dir1);
-        dir0 == dir1
+    /// Decodes `bytes` and converts the image to ASCII art that fits within `self.area`.
+    fn convert(&self, bytes: Vec<u8>) -> Option<(DynamicImage, Text<'static>)> {
+        let dyn_img = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .inspect_err(|err| warn!("error guessing image format: {:?}", err))
+            .ok()?
+            .decode()
+            .inspect_err(|err| warn!("error decoding image: {:?}", err))
+            .ok()?;
+        self.convert_image(dyn_img, &mut None)
     }
 
-    fn update_app_state(&mut self) -> Result<()> {
-        let mut new_img_bytes = None;
-        if self.client.is_none() {
-            assert!(self.state.img_state.is_fetching());
-            match self.state.img_state.try_finish_fetching() {
-                None => {
-                    // Blocked waiting for image download
-                    return Ok(());
-                }
-                Some((client, new_bytes)) => {
-                    self.state.img_state.set_idle(None);
-                    new_img_bytes = new_bytes;
-                    self.client = Some(client);
+    /// Converts each frame of an animated GIF or APNG, or returns `None` if `bytes` isn't one.
+    /// The first frame is returned with the animation. Frames are decoded one at a time, and
+    /// all of them together must fit within the memory limit.
+    fn convert_animation(
+        &self,
+        bytes: &[u8],
+    ) -> std::result::Result<Option<(DynamicImage, Animation)>, String> {
+        let frames = match image::guess_format(bytes) {
+            Ok(ImageFormat::Gif) => match GifDecoder::new(Cursor::new(bytes)) {
+                Ok(decoder) => decoder.into_frames(),
+                Err(_) => return Ok(None),
+            },
+            Ok(ImageFormat::Png) => match PngDecoder::new(Cursor::new(bytes)) {
+                Ok(decoder) if decoder.is_apng() => decoder.apng().into_frames(),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        let mut first_img = None;
+        let mut texts = Vec::new();
+        let mut delays = Vec::new();
+        // Every frame is cropped like the first, so that the art doesn't jump around.
+        let mut margins = None;
+        for frame in frames {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(err) => {
+                    warn!("error decoding animation frames: {:?}", err);
+                    return Ok(None);
                 }
-            }
+            };
+            let (w, h) = frame.buffer().dimensions();
+            self.check_frames_memory(w, h, texts.len() + 1)?;
+            delays.push(Duration::from(frame.delay()));
+            let dyn_img = DynamicImage::ImageRgba8(frame.into_buffer());
+            let Some((img, text)) = self.convert_image(dyn_img, &mut margins) else {
+                return Ok(None);
+            };
+            first_img.get_or_insert(img);
+            texts.push(text);
         }
+        if texts.len() < 2 {
+            return Ok(None);
+        }
+        info!("converted {} animation frames", texts.len());
+        Ok(first_img.map(|img| (img, Animation::new(texts, delays))))
+    }
 
-        let client = self.client.as_mut().unwrap();
-        self.state.mpd_status = client.status()?;
-        let old_song = self.state.current_song.take();
-        let new_song = client.currentsong()?;
-        let album_art_changed = match (&old_song, &new_song) {
-            (None, None) => false,
-            (Some(song0), Some(song1)) if song0 == song1 => false,
-            (Some(song0), Some(song1)) => !Self::songs_in_same_dir(song0, song1),
-            _ => true,
+    /// Crops, filters and converts a decoded image to fit within `self.area`. The margins
+    /// found by --auto-crop are stored in `margins`, or reused if it's already set.
+    fn convert_image(
+        &self,
+        dyn_img: DynamicImage,
+        margins: &mut Option<Crop>,
+    ) -> Option<(DynamicImage, Text<'static>)> {
+        let dyn_img = match self.options.crop {
+            Some(crop) => crop.apply(dyn_img),
+            None => dyn_img,
+        };
+        let dyn_img = match self.options.auto_crop_tolerance {
+            Some(tolerance) => margins
+                .get_or_insert_with(|| {
+                    let (top, right, bottom, left) = auto_crop(&dyn_img, tolerance);
+                    Crop {
+                        top,
+                        right,
+                        bottom,
+                        left,
+                    }
+                })
+                .apply(dyn_img),
+            None => dyn_img,
+        };
+        let dyn_img = image_processing::reduce_noise(dyn_img, self.options.noise_reduction);
+        let mut dyn_img = image_processing::sharpen(dyn_img, self.options.sharpen);
+        if self.options.edge_detect {
+            dyn_img = image_processing::edge_detect(&dyn_img);
+        }
+        if self.options.invert {
+            dyn_img.invert();
+        }
+        let dyn_img = effects::apply_effects(dyn_img, &self.options.effects);
+        let (viewable_width, viewable_height) = self.viewable_size();
+        if viewable_width == 0 || viewable_height == 0 {
+            warn!("viewport {} is too small to show album art", self.area);
+            return None;
+        }
+        let image_aspect = dyn_img.width() as f64 / dyn_img.height() as f64;
+        info!(
+            "image: {} x {}; aspect: {}",
+            dyn_img.width(),
+            dyn_img.height(),
+            image_aspect
+        );
+        let width = self.compute_ascii_width(image_aspect);
+        let width = ((width as f64 * self.options.zoom).round() as usize).max(1);
+        let width = match self.options.align_multiple {
+            // Narrower than one multiple, the width is left as is rather than widened past it.
+            Some(multiple) => match width - width % multiple {
+                0 => width,
+                aligned => aligned,
+            },
+            None => width,
         };
+        info!("scaled ascii image width: {}", width);
+        let text = self.convert_to_width(&dyn_img, width)?;
+        Some((dyn_img, text))
+    }
 
-        self.state.current_song = new_song;
-        if album_art_changed {
-            debug!("album_art_changed!");
-            // drop the image bytes, if any, that we just fetched.
-            new_img_bytes.take();
-            self.state
-                .img_state
-                .start_fetching(self.client.take().unwrap(), self.state.current_song.clone());
-        } else if new_img_bytes.is_some() {
-            self.state.img_state.start_converting(
-                new_img_bytes.unwrap(),
-                ConversionContext {
-                    area: self.state.viewport_area,
-                    font: self.font.clone(),
-                    font_aspect: self.font_aspect,
-                },
-            );
-        } else if self.state.img_state.is_converting() {
-            match self.state.img_state.try_finish_converting() {
-                v @ Some(_) => self.state.img_state.set_idle(v),
-                None => (),
-            }
+    /// Columns and rows of the area within the viewport that art is shown in.
+    fn viewable_size(&self) -> (usize, usize) {
+        let (horiz_margin, vert_margin) = if self.tile {
+            (HORIZ_BORDER_WIDTH, VERT_BORDER_WIDTH)
+        } else {
+            (
+                HORIZ_VIEWPORT_GAP + HORIZ_BORDER_WIDTH + HORIZ_PADDING,
+                VERT_VIEWPORT_GAP + VERT_BORDER_WIDTH + VERT_PADDING,
+            )
+        };
+        let viewable_width = (self.area.width as usize).saturating_sub(horiz_margin * 2);
+        let viewable_height = (self.area.height as usize).saturating_sub(vert_margin * 2);
+        (viewable_width, viewable_height)
+    }
+
+    /// Width in cells to convert an image with aspect ratio `image_aspect` to, before zooming,
+    /// so that it fits the viewport as --scale-to-fit says.
+    fn compute_ascii_width(&self, image_aspect: f64) -> usize {
+        let (viewable_width, viewable_height) = self.viewable_size();
+        let viewport_aspect = viewable_width as f64 * self.font_aspect / viewable_height as f64;
+        info!("viewport: {}; aspect: {}", self.area, viewport_aspect);
+        let fit_width = match self.options.scale_to_fit {
+            ScaleToFit::Contain => image_aspect > viewport_aspect,
+            ScaleToFit::Cover => image_aspect <= viewport_aspect,
+            ScaleToFit::Width => true,
+            ScaleToFit::Height => false,
+        };
+        if fit_width {
+            // For `contain`, the image is wide compared to the viewport, so width will be the
+            // determining factor when scaling.
+            viewable_width
+        } else {
+            // For `contain`, the image is tall compared to the viewport, so height will be the
+            // determining factor when scaling.
+            //
+            // (VERT_VIEWPORT_GAP + VERT_BORDER_WIDTH + VERT_PADDING) * 2 + ascii_img_width * font_aspect / img_aspect ==
+            //   viewport_height
+            //
+            // ascii_img_height == ascii_img_width * font_aspect / img_aspect
+            // Solving for width:
+            //
+            // width = (viewport_height - ((VERT_VIEWPORT_GAP + VERT_BORDER_WIDTH + VERT_PADDING) * 2)) / font_aspect;
+            (viewable_height as f64 * image_aspect / self.font_aspect) as usize
         }
-        Ok(())
     }
 
-    fn elapsed_since_update(&self) -> Duration {
-        if self.last_update_time.is_none() {
-            return Self::UPDATE_PERIOD;
+    /// Converts the image to text `width` cells wide, in the configured mode.
+    fn convert_to_width(&self, dyn_img: &DynamicImage, width: usize) -> Option<Text<'static>> {
+        match self.options.mode {
+            ConversionMode::Ascii => (),
+            ConversionMode::Braille => return Some(self.convert_braille(dyn_img, width)),
+            ConversionMode::HalfBlock => return Some(self.convert_half_block(dyn_img, width)),
         }
+        let sampled = image_processing::subsample(dyn_img, self.options.pixel_sample_rate);
+        let rows = convert::img_to_char_rows(
+            &self.font,
+            &LumaImage::from(sampled.as_ref()),
+            get_converter("direction-and-intensity"),
+            Some(width),
+            0.0,
+            &get_conversion_algorithm("edge-augmented"),
+        );
+        convert::char_rows_to_terminal_color_string(&rows, &sampled)
+            .into_text()
+            .inspect_err(|err| warn!("error converting ANSI to `Text`: {:?}", err))
+            .ok()
+    }
 
-        Instant::now().duration_since(self.last_update_time.unwrap())
+    /// Number of rows of cells needed to show the image `width` cells wide.
+    fn scaled_height(&self, dyn_img: &DynamicImage, width: usize) -> usize {
+        (dyn_img.height() as f64 / dyn_img.width() as f64 * width as f64 * self.font_aspect)
+            .round()
+            .max(1.0) as usize
     }
 
-    fn song_desc(&self) -> String {
-        self.state
-            .current_song
-            .as_ref()
-            .map(|song| {
-                format!(
-                    "{} - {}",
-                    song.artist.as_deref().unwrap_or("Unknown artist"),
-                    song.title.as_deref().unwrap_or("Unknown song")
-                )
+    /// Converts the image to Braille characters `width` cells wide, colored with the average
+    /// color of the pixels each character covers.
+    fn convert_braille(&self, dyn_img: &DynamicImage, width: usize) -> Text<'static> {
+        let height = self.scaled_height(dyn_img, width);
+        let resized = dyn_img.resize_exact(
+            (width * 2) as u32,
+            (height * 4) as u32,
+            FilterType::Triangle,
+        );
+        let luma = resized.to_luma8();
+        let rgb = resized.to_rgb8();
+
+        let lines: Vec<Line> = (0..height as u32)
+            .map(|row| {
+                let spans: Vec<Span> = (0..width as u32)
+                    .map(|col| {
+                        let (x0, y0) = (col * 2, row * 4);
+                        let mut pixels = [[false; 4]; 2];
+                        for (dx, column) in pixels.iter_mut().enumerate() {
+                            for (dy, pixel) in column.iter_mut().enumerate() {
+                                let [l] = luma.get_pixel(x0 + dx as u32, y0 + dy as u32).0;
+                                *pixel = luminance_to_braille_bit(
+                                    l as f32 / 255.0,
+                                    self.options.braille_threshold,
+                                );
+                            }
+                        }
+                        let color = Self::average_color(&rgb, x0, y0, 2, 4);
+                        Span::styled(
+                            braille_encode(&pixels).to_string(),
+                            Style::default().fg(color),
+                        )
+                    })
+                    .collect();
+                Line::from(spans)
             })
-            .unwrap_or("No song playing".to_owned())
+            .collect();
+        Text::from(lines)
     }
 
-    fn fmt_duration(d: &Duration) -> String {
-        let s = d.as_secs();
-        format!("{:02}:{:02}", s / 60, s % 60)
+    /// Converts the image to `▀` characters `width` cells wide, with the upper pixel as the
+    /// foreground color and the lower pixel as the background color.
+    fn convert_half_block(&self, dyn_img: &DynamicImage, width: usize) -> Text<'static> {
+        let height = self.scaled_height(dyn_img, width);
+        let rgb = dyn_img
+            .resize_exact(width as u32, (height * 2) as u32, FilterType::Triangle)
+            .to_rgb8();
+        let color = |x, y| {
+            let Rgb([r, g, b]) = *rgb.get_pixel(x, y);
+            Color::Rgb(r, g, b)
+        };
+
+        let lines: Vec<Line> = (0..height as u32)
+            .map(|row| {
+                let spans: Vec<Span> = (0..width as u32)
+                    .map(|col| {
+                        let style = Style::default()
+                            .fg(color(col, row * 2))
+                            .bg(color(col, row * 2 + 1));
+                        Span::styled("▀", style)
+                    })
+                    .collect();
+                Line::from(spans)
+            })
+            .collect();
+        Text::from(lines)
+    }
+
+    fn average_color(img: &RgbImage, x0: u32, y0: u32, width: u32, height: u32) -> Color {
+        let mut sum = [0u32; 3];
+        for y in y0..y0 + height {
+            for x in x0..x0 + width {
+                let Rgb(pixel) = img.get_pixel(x, y);
+                for (total, channel) in sum.iter_mut().zip(pixel) {
+                    *total += *channel as u32;
+                }
+            }
+        }
+        let n = width * height;
+        Color::Rgb((sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8)
+    }
+}
+
+/// Whether a pixel with luminance `luma` (0.0–1.0) should be drawn as a raised Braille dot.
+fn luminance_to_braille_bit(luma: f32, threshold: f32) -> bool {
+    luma > threshold
+}
+
+/// Finds the width of the solid-color border around `img`, as (top, right, bottom, left). Each
+/// edge is scanned inward until a pixel differs from the top left pixel by more than
+/// `tolerance` in any channel.
+fn auto_crop(img: &DynamicImage, tolerance: u8) -> (u32, u32, u32, u32) {
+    let rgb = img.to_rgb8();
+    let (w, h) = rgb.dimensions();
+    if w == 0 || h == 0 {
+        return (0, 0, 0, 0);
+    }
+    let corner = *rgb.get_pixel(0, 0);
+    let is_border = |x: u32, y: u32| {
+        rgb.get_pixel(x, y)
+            .0
+            .iter()
+            .zip(corner.0)
+            .all(|(c, corner_c)| c.abs_diff(corner_c) <= tolerance)
+    };
+    let row_is_border = |y: u32| (0..w).all(|x| is_border(x, y));
+    let col_is_border = |x: u32| (0..h).all(|y| is_border(x, y));
+
+    let top = (0..h).take_while(|&y| row_is_border(y)).count() as u32;
+    if top == h {
+        // The whole image is one color.
+        return (0, 0, 0, 0);
+    }
+    let bottom = (0..h).rev().take_while(|&y| row_is_border(y)).count() as u32;
+    let left = (0..w).take_while(|&x| col_is_border(x)).count() as u32;
+    let right = (0..w).rev().take_while(|&x| col_is_border(x)).count() as u32;
+    (top, right, bottom, left)
+}
+
+/// Estimates the memory, in bytes, used by a decoded `w` by `h` image with four bytes per
+/// pixel.
+fn estimate_decode_memory(w: u32, h: u32) -> usize {
+    w as usize * h as usize * 4
+}
+
+/// Encodes a 2x4 block of pixels, indexed as `pixels[column][row]`, as a Braille character
+/// (U+2800–U+28FF) with a dot raised for each pixel that is on.
+fn braille_encode(pixels: &[[bool; 4]; 2]) -> char {
+    // Bit for each dot, by column and row. Dots 7 and 8 were added to the bottom row later,
+    // hence the irregular numbering.
+    const DOT_BITS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+    let mut bits = 0;
+    for (column, column_bits) in pixels.iter().zip(DOT_BITS) {
+        for (&pixel, bit) in column.iter().zip(column_bits) {
+            if pixel {
+                bits |= bit;
+            }
+        }
+    }
+    char::from_u32(0x2800 + bits).expect("Braille patterns are valid chars")
+}
+
+/// Album art for one of the albums in the queue, in `--playlist-art` mode.
+struct PlaylistTile {
+    song: Song,
+    art: TileArt,
+}
+
+enum TileArt {
+    Loading(JoinHandle<Option<Text<'static>>>),
+    Done(Option<Text<'static>>),
+}
+
+impl TileArt {
+    fn try_finish(&mut self) {
+        if !matches!(self, TileArt::Loading(jh) if jh.is_finished()) {
+            return;
+        }
+        if let TileArt::Loading(jh) = std::mem::replace(self, TileArt::Done(None)) {
+            *self = TileArt::Done(
+                jh.join()
+                    .inspect_err(|err| warn!("error joining playlist art thread: {:?}", err))
+                    .ok()
+                    .flatten(),
+            );
+        }
+    }
+}
+
+/// Collects the settings of an [`App`], which `build` then connects to MPD with. Anything not
+/// set keeps the default of the corresponding command line option.
+struct AppBuilder {
+    host: String,
+    port: u16,
+    /// Requested size in bytes of the socket receive buffer.
+    recv_buffer_size: usize,
+    encoding: TagEncoding,
+    #[cfg(feature = "tls")]
+    mpd_tls: Option<MpdTls>,
+    /// Number of times to try the initial connection, or 0 for no limit.
+    connect_attempts: u32,
+    /// The only address family to connect with, if restricted.
+    ip_family: Option<IpFamily>,
+    font_height: usize,
+    font_width: usize,
+    intervals: Intervals,
+    theme: Theme,
+    display: DisplayOptions,
+    conversion: ConversionOptions,
+    keymap: Option<Keymap>,
+    play_on_start: bool,
+    resume_on_start: bool,
+    play_random_on_empty_queue: bool,
+    /// The tag and value the songs added by `play_random_on_empty_queue` must have, if any.
+    play_random_tag: Option<(RandomTag, String)>,
+    /// Address to serve the app's state on, if any.
+    listen: Option<SocketAddr>,
+    websocket: bool,
+    ssh_tunnel: Option<SshTunnel>,
+}
+
+impl Default for AppBuilder {
+    fn default() -> Self {
+        AppBuilder {
+            host: "localhost".to_string(),
+            port: 6600,
+            recv_buffer_size: 65536,
+            encoding: TagEncoding::Utf8,
+            #[cfg(feature = "tls")]
+            mpd_tls: None,
+            connect_attempts: 1,
+            ip_family: None,
+            font_height: 15,
+            font_width: 8,
+            intervals: Intervals::default(),
+            theme: Theme::default(),
+            display: DisplayOptions::default(),
+            conversion: ConversionOptions::default(),
+            keymap: None,
+            play_on_start: false,
+            resume_on_start: false,
+            play_random_on_empty_queue: false,
+            play_random_tag: None,
+            listen: None,
+            websocket: false,
+            ssh_tunnel: None,
+        }
+    }
+}
+
+impl AppBuilder {
+    fn host(mut self, host: String) -> Self {
+        self.host = host;
+        self
+    }
+
+    fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    fn recv_buffer_size(mut self, recv_buffer_size: usize) -> Self {
+        self.recv_buffer_size = recv_buffer_size;
+        self
+    }
+
+    fn encoding(mut self, encoding: TagEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    fn mpd_tls(mut self, mpd_tls: Option<MpdTls>) -> Self {
+        self.mpd_tls = mpd_tls;
+        self
+    }
+
+    fn connect_attempts(mut self, connect_attempts: u32) -> Self {
+        self.connect_attempts = connect_attempts;
+        self
+    }
+
+    fn ip_family(mut self, ip_family: Option<IpFamily>) -> Self {
+        self.ip_family = ip_family;
+        self
+    }
+
+    fn font_height(mut self, font_height: usize) -> Self {
+        self.font_height = font_height;
+        self
+    }
+
+    fn font_width(mut self, font_width: usize) -> Self {
+        self.font_width = font_width;
+        self
+    }
+
+    /// How often the MPD status is polled.
+    fn update_period(mut self, period: Duration) -> Self {
+        self.intervals.status_refresh_period = period;
+        self
+    }
+
+    /// How often the current song's art is checked for changes.
+    fn art_refresh_period(mut self, period: Duration) -> Self {
+        self.intervals.art_refresh_period = period;
+        self
+    }
+
+    fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.intervals.idle_timeout = idle_timeout;
+        self
+    }
+
+    fn event_poll_timeout(mut self, timeout: Duration) -> Self {
+        self.intervals.event_poll_timeout = timeout;
+        self
+    }
+
+    fn startup_fetch_delay(mut self, delay: Duration) -> Self {
+        self.intervals.startup_fetch_delay = delay;
+        self
+    }
+
+    fn connection_check_period(mut self, period: Option<Duration>) -> Self {
+        self.intervals.connection_check_period = period;
+        self
+    }
+
+    fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    fn display(mut self, display: DisplayOptions) -> Self {
+        self.display = display;
+        self
+    }
+
+    fn conversion(mut self, conversion: ConversionOptions) -> Self {
+        self.conversion = conversion;
+        self
+    }
+
+    fn keymap(mut self, keymap: Keymap) -> Self {
+        self.keymap = Some(keymap);
+        self
+    }
+
+    /// Whether to start playing if MPD is stopped once connected.
+    fn play_on_start(mut self, play_on_start: bool) -> Self {
+        self.play_on_start = play_on_start;
+        self
+    }
+
+    /// Whether to resume playing if MPD is paused once connected.
+    fn resume_on_start(mut self, resume_on_start: bool) -> Self {
+        self.resume_on_start = resume_on_start;
+        self
+    }
+
+    /// Whether to fill an empty queue and play it in random order once connected, optionally
+    /// with only the songs having a tag.
+    fn play_random_on_empty_queue(
+        mut self,
+        enabled: bool,
+        tag: Option<(RandomTag, String)>,
+    ) -> Self {
+        self.play_random_on_empty_queue = enabled;
+        self.play_random_tag = tag;
+        self
+    }
+
+    fn listen(mut self, addr: Option<SocketAddr>) -> Self {
+        self.listen = addr;
+        self
+    }
+
+    /// Whether the --listen address also streams the state over WebSocket.
+    fn websocket(mut self, websocket: bool) -> Self {
+        self.websocket = websocket;
+        self
+    }
+
+    fn ssh_tunnel(mut self, ssh_tunnel: Option<SshTunnel>) -> Self {
+        self.ssh_tunnel = ssh_tunnel;
+        self
+    }
+
+    fn build(self) -> Result<App> {
+        let keymap = match self.keymap {
+            Some(keymap) => keymap,
+            None => Keymap::new(&HashMap::new())?,
+        };
+        let host_port = format!("{}:{}", self.host, self.port);
+        let mut addrs_iter = host_port.to_socket_addrs()?;
+        let addr = match self.ip_family {
+            None => addrs_iter.next().ok_or("could not resolve host")?,
+            Some(family) => addrs_iter
+                .find(|addr| family.matches(addr))
+                .ok_or_else(|| format!("no {} address found for {}", family, host_port))?,
+        };
+
+        let connect = ConnectOptions {
+            recv_buffer_size: self.recv_buffer_size,
+            encoding: self.encoding,
+            #[cfg(feature = "tls")]
+            tls: self.mpd_tls.map(Arc::new),
+        };
+        let mut attempt = 1;
+        let mut client = loop {
+            match connect_mpd(addr, &connect) {
+                Ok(client) => break client,
+                Err(err) if attempt != self.connect_attempts => {
+                    warn!(
+                        "connection attempt {} to {} failed: {:?}",
+                        attempt, addr, err
+                    );
+                    attempt += 1;
+                    std::thread::sleep(Duration::from_secs(1));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        };
+        if self.play_random_on_empty_queue {
+            if let Err(err) = play_random(&mut client, self.play_random_tag.as_ref()) {
+                warn!("error playing random songs: {:?}", err);
+            }
+        }
+        if self.play_on_start || self.resume_on_start {
+            let result = client.status().and_then(|status| match status.state {
+                MpdState::Stop if self.play_on_start => {
+                    info!("MPD is stopped; starting playback");
+                    client.play()
+                }
+                MpdState::Pause if self.resume_on_start => {
+                    info!("MPD is paused; resuming playback");
+                    client.pause(false)
+                }
+                _ => Ok(()),
+            });
+            if let Err(err) = result {
+                warn!("error starting playback: {:?}", err);
+            }
+        }
+        let snapshot = match self.listen {
+            None => None,
+            Some(addr) => {
+                let snapshot = server::Snapshot::default();
+                server::spawn(addr, snapshot.clone(), self.websocket)?;
+                Some(snapshot)
+            }
+        };
+        #[cfg(feature = "visualizer")]
+        let visualizer = self
+            .display
+            .visualizer_fifo
+            .clone()
+            .map(visualizer::Visualizer::spawn);
+        let font = App::load_font(self.font_height, self.font_width);
+        let font_aspect = font.width as f64 / font.height as f64;
+        info!(
+            "font has width {} and height {}; aspect: {}",
+            font.width, font.height, font_aspect
+        );
+
+        let help_dismiss_at = self.display.help_duration.map(|d| Instant::now() + d);
+        let background_image = match &self.display.background_image {
+            None => None,
+            Some(path) => Some(
+                ImageReader::open(path)
+                    .map_err(|err| format!("error opening {}: {}", path.display(), err))?
+                    .decode()
+                    .map_err(|err| format!("error decoding {}: {}", path.display(), err))?,
+            ),
+        };
+
+        Ok(App {
+            addr,
+            connect,
+            font,
+            font_aspect,
+            client: Some(client),
+            theme: self.theme,
+            keymap,
+            display: self.display,
+            conversion: self.conversion,
+            background_image,
+            background: None,
+            scroll_accumulator: 0.0,
+            last_marquee_update: Instant::now(),
+            scroll_x: 0,
+            scroll_y: 0,
+            state: State::default(),
+            intervals: self.intervals,
+            last_activity: Instant::now(),
+            last_status_update: None,
+            last_successful_status: None,
+            client_lent_at: None,
+            lent_client: None,
+            output_pipe: None,
+            last_art_check: None,
+            last_connection_check: Instant::now(),
+            hooked_song: None,
+            connection_check: None,
+            startup_fetch_at: None,
+            frame_times: VecDeque::with_capacity(App::FPS_WINDOW),
+            skipped_frames: 0,
+            rating_prompt: false,
+            clear_queue_prompt: false,
+            show_help: help_dismiss_at.is_some(),
+            show_playlist: false,
+            help_dismiss_at,
+            snapshot,
+            art_data_url: None,
+            art_data_url_source: None,
+            #[cfg(feature = "visualizer")]
+            visualizer,
+            focused: true,
+            _ssh_tunnel: self.ssh_tunnel,
+            volume_changed_at: None,
+            #[cfg(debug_assertions)]
+            show_debug_overlay: false,
+            #[cfg(debug_assertions)]
+            frame_sequence: 0,
+            exit: false,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    fn matches(&self, addr: &SocketAddr) -> bool {
+        matches!(
+            (self, addr.ip()),
+            (IpFamily::V4, IpAddr::V4(_)) | (IpFamily::V6, IpAddr::V6(_))
+        )
+    }
+}
+
+impl std::fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpFamily::V4 => write!(f, "IPv4"),
+            IpFamily::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// How connections to MPD are set up.
+#[derive(Clone)]
+struct ConnectOptions {
+    /// Requested size in bytes of the socket receive buffer.
+    recv_buffer_size: usize,
+    encoding: TagEncoding,
+    /// Set with --mpd-tls.
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<MpdTls>>,
+}
+
+/// Connects to MPD, asking for a socket receive buffer of `recv_buffer_size` bytes so that
+/// large album art arrives in fewer reads.
+fn connect_mpd(addr: SocketAddr, options: &ConnectOptions) -> mpd::error::Result<MpdClient> {
+    let stream = TcpStream::connect(addr)?;
+    let sock = SockRef::from(&stream);
+    match sock
+        .set_recv_buffer_size(options.recv_buffer_size)
+        .and_then(|()| sock.recv_buffer_size())
+    {
+        Ok(size) => debug!(
+            "socket receive buffer size: {} (requested {})",
+            size, options.recv_buffer_size
+        ),
+        Err(err) => warn!("error setting socket receive buffer size: {:?}", err),
+    }
+    new_mpd_client(stream, options)
+}
+
+/// Starts talking to MPD over `stream`, with TLS if --mpd-tls is set.
+fn new_mpd_client(stream: TcpStream, options: &ConnectOptions) -> mpd::error::Result<MpdClient> {
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &options.tls {
+        let stream = tls
+            .connect(stream)
+            .map_err(|err| std::io::Error::other(err.to_string()))?;
+        let stream = Connection::Tls(Box::new(stream));
+        return MpdClient::new(MpdStream::new(stream, options.encoding));
+    }
+    MpdClient::new(MpdStream::new(Connection::Tcp(stream), options.encoding))
+}
+
+/// How long to wait for an --ssh-tunnel to open.
+const SSH_TUNNEL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs ssh to forward a free local port to `remote_port` on the far side of `user_host`, and
+/// waits until the port accepts connections. Returns the ssh process and the local port.
+fn setup_ssh_tunnel(user_host: &str, remote_port: u16) -> Result<(Child, u16)> {
+    // ssh doesn't report the port it picks for `-L 0:...`, so a free one is found first.
+    let local_port = TcpListener::bind(("127.0.0.1", 0))?.local_addr()?.port();
+    info!(
+        "forwarding port {} to port {} through {}",
+        local_port, remote_port, user_host
+    );
+    let mut child = Command::new("ssh")
+        .args(["-N", "-o", "ExitOnForwardFailure=yes", "-L"])
+        .arg(format!(
+            "127.0.0.1:{}:localhost:{}",
+            local_port, remote_port
+        ))
+        .arg(user_host)
+        .stdin(Stdio::null())
+        .spawn()
+        .map_err(|err| format!("error running ssh: {}", err))?;
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Err(format!("ssh to {} exited with {}", user_host, status).into());
+        }
+        if TcpStream::connect(("127.0.0.1", local_port)).is_ok() {
+            return Ok((child, local_port));
+        }
+        if start.elapsed() >= SSH_TUNNEL_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("timed out opening SSH tunnel to {}", user_host).into());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// The ssh process of an --ssh-tunnel, killed when dropped so that it exits with the viewer.
+struct SshTunnel(Child);
+
+impl Drop for SshTunnel {
+    fn drop(&mut self) {
+        info!("closing SSH tunnel");
+        if let Err(err) = self.0.kill() {
+            warn!("error stopping ssh: {:?}", err);
+        }
+        let _ = self.0.wait();
+    }
+}
+
+/// Whether MPD at `addr` answers a ping on a new connection within `timeout`. A connection
+/// that has gone stale can block forever instead of failing, so a fresh one is used to tell.
+fn check_mpd_alive(addr: SocketAddr, options: &ConnectOptions, timeout: Duration) -> bool {
+    let result = TcpStream::connect_timeout(&addr, timeout)
+        .and_then(|stream| {
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            Ok(stream)
+        })
+        .map_err(mpd::error::Error::from)
+        .and_then(|stream| new_mpd_client(stream, options))
+        .and_then(|mut client| client.ping());
+    if let Err(err) = &result {
+        warn!("MPD connection check failed: {:?}", err);
+    }
+    result.is_ok()
+}
+
+struct Intervals {
+    status_refresh_period: Duration,
+    art_refresh_period: Duration,
+    /// How long without a keypress the app goes idle after, if at all.
+    idle_timeout: Option<Duration>,
+    event_poll_timeout: Duration,
+    /// How long after the first frame is drawn the first album art is fetched.
+    startup_fetch_delay: Duration,
+    /// How often to check that MPD still accepts connections, if at all.
+    connection_check_period: Option<Duration>,
+}
+
+impl Default for Intervals {
+    fn default() -> Self {
+        Intervals {
+            status_refresh_period: Duration::from_secs(1),
+            art_refresh_period: Duration::from_secs(5),
+            idle_timeout: Some(Duration::from_secs(60)),
+            event_poll_timeout: Duration::from_millis(5),
+            startup_fetch_delay: Duration::ZERO,
+            connection_check_period: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+struct DisplayOptions {
+    scroll_lyrics: bool,
+    /// Title scroll speed in characters per second, if the title marquee is enabled.
+    marquee_speed: Option<f64>,
+    /// Maximum number of lines the song title wraps onto.
+    title_lines: usize,
+    /// Columns each character of the song title takes, 1 or 2.
+    title_scale: usize,
+    /// Height of the status bar below the album art, or 0 to show the status in the border.
+    status_bar_height: u16,
+    stream_title_tag: String,
+    /// Maximum number of albums to show, if the playlist art grid is enabled.
+    playlist_art_max: Option<usize>,
+    background_image: Option<PathBuf>,
+    /// Shell command the album art is piped to after each conversion.
+    output_pipe: Option<String>,
+    /// Shell command run whenever the playing song changes.
+    song_change_hook: Option<String>,
+    /// Shell command whose output is used as album art.
+    art_fetch_hook: Option<String>,
+    /// Album art sources, in the order they're tried.
+    art_priority: Vec<ArtSourceKind>,
+    album_art_max_fetch_bytes: usize,
+    /// Key for Last.fm's API, set if --last-fm-art is.
+    lastfm_api_key: Option<String>,
+    musicbrainz_art: bool,
+    /// Token for Discogs's API, set if --discogs-art is.
+    discogs_token: Option<String>,
+    /// Spotify's API, set if --spotify-art is, shared so its token outlives each fetch.
+    spotify: Option<Arc<Spotify>>,
+    no_ansi_output: bool,
+    metrics_file: Option<PathBuf>,
+    /// Directory new art is saved to with --snapshot-on-change.
+    snapshot_dir: Option<PathBuf>,
+    snapshot_max_files: usize,
+    show_queue_position: bool,
+    show_next_song: bool,
+    art_placeholder: Option<char>,
+    shuffle_art_colors: bool,
+    dim_on_pause: bool,
+    art_border_padding_char: Option<char>,
+    /// Text shown in the bottom-right corner of the art.
+    watermark: Option<String>,
+    watermark_color: Option<Color>,
+    max_title_length: Option<usize>,
+    /// Form the tags are normalized to for display; songs are still compared as MPD sent them.
+    unicode_normalize: NormalizationForm,
+    /// Songs shorter than this keep the previous song's art.
+    skip_short_songs: Option<Duration>,
+    status_show_bitrate: bool,
+    hide_status_when_stopped: bool,
+    volume_display_style: VolumeDisplayStyle,
+    compact_mode: bool,
+    seek_step: Duration,
+    mouse_scroll_seeks: bool,
+    clear_queue_on_exit: bool,
+    export_format: ExportFormat,
+    export_jpeg_quality: u8,
+    /// Whether the art is converted again to fit when the terminal is resized.
+    resize_on_window_change: bool,
+    pause_fetch_when_hidden: bool,
+    /// Whether song ratings are read from and saved to MPD stickers.
+    enable_stickers: bool,
+    /// Number of directories, counting up from a song's own, that are compared to decide
+    /// whether two songs are on the same album.
+    album_dir_depth: usize,
+    fps_counter: bool,
+    /// Only every this many passes of the event loop are drawn while art is converting.
+    frame_skip: u32,
+    tiling_mode: bool,
+    preload_next_art: bool,
+    /// Whether the previous album's art is shown next to the current one.
+    compare: bool,
+    /// How long to show the keybinding help at startup, if at all.
+    help_duration: Option<Duration>,
+    /// How long to show errors for, if they aren't shown until a key is pressed.
+    error_display_duration: Option<Duration>,
+    mark_current_playlist_song: bool,
+    #[cfg(debug_assertions)]
+    frame_dump_dir: Option<PathBuf>,
+    /// FIFO the --visualizer reads audio from, if it's shown.
+    #[cfg(feature = "visualizer")]
+    visualizer_fifo: Option<PathBuf>,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        DisplayOptions {
+            scroll_lyrics: false,
+            marquee_speed: None,
+            title_lines: 1,
+            title_scale: 1,
+            status_bar_height: 0,
+            stream_title_tag: "TITLE".to_string(),
+            playlist_art_max: None,
+            background_image: None,
+            output_pipe: None,
+            song_change_hook: None,
+            art_fetch_hook: None,
+            art_priority: ArtSourceKind::default_priority(false, false),
+            album_art_max_fetch_bytes: 20 * 1024 * 1024,
+            lastfm_api_key: None,
+            musicbrainz_art: false,
+            discogs_token: None,
+            spotify: None,
+            no_ansi_output: false,
+            metrics_file: None,
+            snapshot_dir: None,
+            snapshot_max_files: 100,
+            show_queue_position: false,
+            show_next_song: false,
+            art_placeholder: None,
+            shuffle_art_colors: false,
+            dim_on_pause: false,
+            art_border_padding_char: None,
+            watermark: None,
+            watermark_color: None,
+            max_title_length: None,
+            unicode_normalize: NormalizationForm::Nfc,
+            skip_short_songs: None,
+            status_show_bitrate: false,
+            hide_status_when_stopped: false,
+            volume_display_style: VolumeDisplayStyle::Both,
+            compact_mode: false,
+            seek_step: Duration::from_secs(5),
+            mouse_scroll_seeks: false,
+            clear_queue_on_exit: false,
+            export_format: ExportFormat::Png,
+            export_jpeg_quality: 85,
+            resize_on_window_change: true,
+            pause_fetch_when_hidden: false,
+            enable_stickers: false,
+            album_dir_depth: 1,
+            fps_counter: false,
+            frame_skip: 1,
+            tiling_mode: false,
+            preload_next_art: false,
+            compare: false,
+            help_duration: None,
+            error_display_duration: None,
+            mark_current_playlist_song: true,
+            #[cfg(debug_assertions)]
+            frame_dump_dir: None,
+            #[cfg(feature = "visualizer")]
+            visualizer_fifo: None,
+        }
+    }
+}
+
+/// Size, in cells, of each sample printed by --ascii-charset-preview.
+const PREVIEW_WIDTH: usize = 20;
+const PREVIEW_HEIGHT: usize = 10;
+
+/// Prints a gradient converted with each conversion mode, labeled with the flag that selects
+/// it.
+fn print_charset_preview(font: Font, options: ConversionOptions) {
+    let font_aspect = font.width as f64 / font.height as f64;
+    // Sized so that the samples come out PREVIEW_HEIGHT rows tall.
+    let width = 200;
+    let height = (width as f64 * PREVIEW_HEIGHT as f64 / PREVIEW_WIDTH as f64 / font_aspect) as u32;
+    let gradient = DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+        Luma([((x * height + y * width) * 255 / (width * height * 2)) as u8])
+    }));
+
+    let modes = [
+        (ConversionMode::Ascii, "ASCII (default)"),
+        (ConversionMode::Braille, "Braille (--pixel-art-mode)"),
+        (ConversionMode::HalfBlock, "Half blocks (--half-block-mode)"),
+    ];
+    for (mode, label) in modes {
+        let context = ConversionContext {
+            area: Rect::default(),
+            font: font.clone(),
+            font_aspect,
+            options: ConversionOptions {
+                mode,
+                ..options.clone()
+            },
+            tile: false,
+        };
+        println!("{}:", label);
+        match context.convert_to_width(&gradient, PREVIEW_WIDTH) {
+            Some(text) => println!("{}", text_to_ansi(&text)),
+            None => println!("(conversion failed)"),
+        }
+        println!();
+    }
+}
+
+/// A line of --metrics-file, describing how the art for a song was loaded.
+#[derive(Serialize)]
+struct Metric<'a> {
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+    song_file: &'a str,
+    /// Missing if the art was preloaded, or reconverted after a resize or with ctrl+r rather
+    /// than fetched.
+    fetch_ms: Option<u128>,
+    convert_ms: Option<u128>,
+    image_w: u32,
+    image_h: u32,
+    ascii_w: usize,
+    ascii_h: usize,
+    /// Whether preloaded art was used instead of fetching it when the song changed.
+    cache_hit: bool,
+}
+
+/// The app's state as served by --listen.
+#[derive(Serialize)]
+struct StateSnapshot<'a> {
+    song: Option<SongSnapshot<'a>>,
+    /// "play", "pause" or "stop".
+    state: &'a str,
+    elapsed_secs: Option<f64>,
+    duration_secs: Option<f64>,
+    /// From 0 to 100, or -1 if MPD has no mixer.
+    volume: i8,
+    viewport_width: u16,
+    viewport_height: u16,
+}
+
+#[derive(Serialize)]
+struct SongSnapshot<'a> {
+    file: &'a str,
+    artist: Option<&'a str>,
+    title: Option<&'a str>,
+    album: Option<&'a str>,
+}
+
+/// Appends `m` to the file at `path` as a line of JSON.
+fn write_metric(path: &Path, m: &Metric) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let mut line = serde_json::to_vec(m)?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    Ok(())
+}
+
+/// `secs` since the Unix epoch as a UTC time in ISO 8601's basic format, e.g.
+/// 20240131T235959Z, which unlike the extended format is safe in file names everywhere.
+fn iso8601_basic(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+    // Howard Hinnant's civil_from_days.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// `name` with everything but letters, digits and dashes replaced by underscores.
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Deletes the least recently modified PNGs in `dir` until at most `max_files` are left.
+fn remove_old_snapshots(dir: &Path, max_files: usize) -> Result<()> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "png"))
+        .collect();
+    if entries.len() <= max_files {
+        return Ok(());
+    }
+    entries.sort_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok());
+    for entry in &entries[..entries.len() - max_files] {
+        debug!("removing old snapshot {}", entry.path().display());
+        std::fs::remove_file(entry.path())?;
+    }
+    Ok(())
+}
+
+/// Saves `img` to `path`, with `jpeg_quality` used if the format is JPEG.
+fn export_image(
+    img: &DynamicImage,
+    path: &Path,
+    format: ExportFormat,
+    jpeg_quality: u8,
+) -> Result<()> {
+    match format {
+        ExportFormat::Jpg => {
+            let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            // JPEG has no alpha channel.
+            JpegEncoder::new_with_quality(file, jpeg_quality).encode_image(&img.to_rgb8())?;
+        }
+        _ => img.save_with_format(path, format.image_format())?,
+    }
+    Ok(())
+}
+
+fn verbosity_to_filter(n: u8) -> log::LevelFilter {
+    match n {
+        0 => log::LevelFilter::Off,
+        1 => log::LevelFilter::Error,
+        2 => log::LevelFilter::Warn,
+        3 => log::LevelFilter::Info,
+        4 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    }
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum VolumeDisplayStyle {
+    /// A bar of block characters, e.g. ███████░░░
+    Bar,
+    /// The volume as a percentage
+    Number,
+    /// The bar followed by the percentage
+    Both,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// Text with ANSI color escape codes
+    Ansi,
+    /// An HTML <pre> element with colored spans
+    Html,
+    /// An RTF document
+    Rtf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum CursorStyle {
+    /// A block that doesn't blink
+    SteadyBlock,
+    /// A blinking vertical bar
+    BlinkingBeam,
+    /// No cursor, as with --hide-cursor
+    Hidden,
+}
+
+impl CursorStyle {
+    /// The command that sets the cursor to this style, if it's visible.
+    fn command(self) -> Option<SetCursorStyle> {
+        match self {
+            CursorStyle::SteadyBlock => Some(SetCursorStyle::SteadyBlock),
+            #[cfg(not(windows))]
+            CursorStyle::BlinkingBeam => Some(SetCursorStyle::BlinkingBar),
+            // Blinking is left to the console's own settings on Windows, so the beam is steady.
+            #[cfg(windows)]
+            CursorStyle::BlinkingBeam => Some(SetCursorStyle::SteadyBar),
+            CursorStyle::Hidden => None,
+        }
+    }
+}
+
+/// A tag that can restrict the songs queued by --play-random-on-empty-queue.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum RandomTag {
+    Genre,
+    Artist,
+    Album,
+}
+
+impl RandomTag {
+    /// The name of the tag in MPD's protocol.
+    fn mpd_name(self) -> &'static str {
+        match self {
+            RandomTag::Genre => "genre",
+            RandomTag::Artist => "artist",
+            RandomTag::Album => "album",
+        }
+    }
+}
+
+/// If the queue is empty, fills it with the songs in the library, or those with the given tag,
+/// and plays them in random order.
+fn play_random(
+    client: &mut MpdClient,
+    tag: Option<&(RandomTag, String)>,
+) -> mpd::error::Result<()> {
+    if client.status()?.queue_len > 0 {
+        return Ok(());
+    }
+    match tag {
+        Some((tag, value)) => {
+            let mut query = Query::new();
+            query.and(Term::Tag(tag.mpd_name().into()), value.as_str());
+            client.findadd(&query)?;
+        }
+        None => {
+            client.add("/")?;
+        }
+    }
+    info!(
+        "queue is empty; added {} of the {} songs in the library",
+        client.status()?.queue_len,
+        client.stats()?.songs
+    );
+    client.random(true)?;
+    client.play()
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum ExportFormat {
+    Png,
+    Jpg,
+    Webp,
+}
+
+impl ExportFormat {
+    fn image_format(self) -> ImageFormat {
+        match self {
+            ExportFormat::Png => ImageFormat::Png,
+            ExportFormat::Jpg => ImageFormat::Jpeg,
+            ExportFormat::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+/// Renders `text` as a string, with ANSI escape codes for its colors.
+fn text_to_ansi(text: &Text) -> String {
+    let mut out = String::new();
+    for (i, line) in text.lines.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        for span in &line.spans {
+            let style = line.style.patch(span.style);
+            let codes: Vec<String> = [
+                style.fg.and_then(|c| ansi_color_code(c, 38)),
+                style.bg.and_then(|c| ansi_color_code(c, 48)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            if codes.is_empty() {
+                out.push_str(&span.content);
+            } else {
+                out.push_str(&format!("\x1b[{}m{}\x1b[0m", codes.join(";"), span.content));
+            }
+        }
+    }
+    out
+}
+
+/// Formats `text` as an HTML `<pre>` element, with a `<span>` setting the colors of each colored
+/// span.
+fn text_to_html(text: &Text) -> String {
+    let mut out = String::from("<pre>");
+    for (i, line) in text.lines.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        for span in &line.spans {
+            let style = line.style.patch(span.style);
+            let content = span
+                .content
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            let css: Vec<String> = [
+                style
+                    .fg
+                    .and_then(css_color)
+                    .map(|c| format!("color: {};", c)),
+                style
+                    .bg
+                    .and_then(css_color)
+                    .map(|c| format!("background-color: {};", c)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            if css.is_empty() {
+                out.push_str(&content);
+            } else {
+                out.push_str(&format!(
+                    "<span style=\"{}\">{}</span>",
+                    css.join(" "),
+                    content
+                ));
+            }
+        }
+    }
+    out.push_str("</pre>\n");
+    out
+}
+
+/// `color` as a CSS color. Only RGB colors are converted, since the others depend on the
+/// terminal's palette.
+fn css_color(color: Color) -> Option<String> {
+    match color {
+        Color::Rgb(r, g, b) => Some(format!("rgb({},{},{})", r, g, b)),
+        _ => None,
+    }
+}
+
+/// Gives each RGB color in `text` a hue picked at random from `seed` and the color, keeping
+/// its saturation and lightness, so that a color is replaced by the same one throughout.
+fn recolor_text(mut text: Text<'static>, seed: u64) -> Text<'static> {
+    let recolor = |color: Option<Color>| match color {
+        Some(Color::Rgb(r, g, b)) => {
+            // splitmix64
+            let mut x = seed ^ u64::from_be_bytes([0, 0, 0, 0, 0, r, g, b]);
+            x = x.wrapping_add(0x9E3779B97F4A7C15);
+            x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+            x ^= x >> 31;
+            let (_, saturation, lightness) = rgb_to_hsl(r, g, b);
+            let (r, g, b) = hsl_to_rgb((x % 360) as f64, saturation, lightness);
+            Some(Color::Rgb(r, g, b))
+        }
+        color => color,
+    };
+    for line in &mut text.lines {
+        line.style.fg = recolor(line.style.fg);
+        line.style.bg = recolor(line.style.bg);
+        for span in &mut line.spans {
+            span.style.fg = recolor(span.style.fg);
+            span.style.bg = recolor(span.style.bg);
+        }
+    }
+    text
+}
+
+/// Adds the dim modifier to every span of `text`.
+fn apply_dim(mut text: Text<'static>) -> Text<'static> {
+    for span in text.lines.iter_mut().flat_map(|line| &mut line.spans) {
+        span.style = span.style.add_modifier(Modifier::DIM);
+    }
+    text
+}
+
+/// Hue in degrees, and saturation and lightness from 0 to 1.
+fn rgb_to_hsl(r: u8, g: u8, b: u8) -> (f64, f64, f64) {
+    let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (hue, saturation, lightness)
+}
+
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> (u8, u8, u8) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match (hue / 60.0) as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    let channel = |c: f64| ((c + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    (channel(r), channel(g), channel(b))
+}
+
+/// Formats `text` as an RTF document in a monospaced font, with its RGB colors in the color
+/// table.
+fn text_to_rtf(text: &Text) -> String {
+    let mut colors: Vec<(u8, u8, u8)> = Vec::new();
+    // Index 0 of the color table is the default color.
+    let mut color_index = |color: Option<Color>| match color {
+        Some(Color::Rgb(r, g, b)) => {
+            let i = match colors.iter().position(|&c| c == (r, g, b)) {
+                Some(i) => i,
+                None => {
+                    colors.push((r, g, b));
+                    colors.len() - 1
+                }
+            };
+            i + 1
+        }
+        _ => 0,
+    };
+    let mut body = String::new();
+    for (i, line) in text.lines.iter().enumerate() {
+        if i > 0 {
+            body.push_str("\\line\n");
+        }
+        for span in &line.spans {
+            let style = line.style.patch(span.style);
+            let fg = color_index(style.fg);
+            let bg = color_index(style.bg);
+            body.push_str(&format!("\\cf{}\\highlight{} ", fg, bg));
+            for c in span.content.chars() {
+                match c {
+                    '\\' | '{' | '}' => {
+                        body.push('\\');
+                        body.push(c);
+                    }
+                    ' '..='~' => body.push(c),
+                    _ => {
+                        let mut units = [0; 2];
+                        for unit in c.encode_utf16(&mut units) {
+                            // RTF takes signed 16-bit code units, followed by a replacement
+                            // character for readers without Unicode support.
+                            body.push_str(&format!("\\u{}?", *unit as i16));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let color_table: String = colors
+        .iter()
+        .map(|(r, g, b)| format!("\\red{}\\green{}\\blue{};", r, g, b))
+        .collect();
+    format!(
+        "{{\\rtf1\\ansi\\deff0{{\\fonttbl{{\\f0\\fmodern Courier New;}}}}{{\\colortbl;{}}}\n\\f0 {}}}\n",
+        color_table, body
+    )
+}
+
+/// Fills `area` of `buf` with copies of `tile`, starting from its top left corner.
+fn render_tiled(tile: &Buffer, area: Rect, buf: &mut Buffer) {
+    if tile.area.is_empty() {
+        return;
+    }
+    for y in area.top()..area.bottom() {
+        for x in area.left()..area.right() {
+            *buf.get_mut(x, y) = tile
+                .get(
+                    (x - area.x) % tile.area.width,
+                    (y - area.y) % tile.area.height,
+                )
+                .clone();
+        }
+    }
+}
+
+/// Renders the symbols in `buf` as plain text, one line per row.
+#[cfg(debug_assertions)]
+fn buffer_to_text(buf: &Buffer) -> String {
+    let mut out = String::new();
+    for y in buf.area.top()..buf.area.bottom() {
+        for x in buf.area.left()..buf.area.right() {
+            out.push_str(buf.get(x, y).symbol());
+        }
+        out.push('\n');
+    }
+    strip_ansi(&out)
+}
+
+/// Removes ANSI escape sequences, such as `\x1b[38;2;0;0;0m`, from `s`.
+fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'[').is_some() {
+            // Skip parameter and intermediate bytes, up to and including the final byte.
+            for c in chars.by_ref() {
+                if ('\x40'..='\x7e').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// SGR parameters selecting `color`, where `base` is 38 for the foreground or 48 for the
+/// background.
+fn ansi_color_code(color: Color, base: u8) -> Option<String> {
+    let index = match color {
+        Color::Rgb(r, g, b) => return Some(format!("{};2;{};{};{}", base, r, g, b)),
+        Color::Indexed(i) => i,
+        Color::Black => 0,
+        Color::Red => 1,
+        Color::Green => 2,
+        Color::Yellow => 3,
+        Color::Blue => 4,
+        Color::Magenta => 5,
+        Color::Cyan => 6,
+        Color::Gray => 7,
+        Color::DarkGray => 8,
+        Color::LightRed => 9,
+        Color::LightGreen => 10,
+        Color::LightYellow => 11,
+        Color::LightBlue => 12,
+        Color::LightMagenta => 13,
+        Color::LightCyan => 14,
+        Color::White => 15,
+        Color::Reset => return None,
+    };
+    Some(format!("{};5;{}", base, index))
+}
+
+/// Runs `cmd` with the shell in a new thread, writing `output` to its standard input. The
+/// returned thread finishes once the command has exited.
+fn pipe_output(cmd: String, output: String) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let result = Command::new("sh")
+            .arg("-c")
+            .arg(&cmd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                // Written on its own thread while standard error is read, so that the command
+                // can't block on either pipe. It may exit without reading all of it.
+                let stdin = child.stdin.take();
+                let writer = std::thread::spawn(move || {
+                    if let Some(mut stdin) = stdin {
+                        let _ = stdin.write_all(output.as_bytes());
+                    }
+                });
+                let out = child.wait_with_output();
+                let _ = writer.join();
+                out
+            });
+        match result {
+            Ok(out) if !out.status.success() => warn!(
+                "output pipe `{}` failed with {}: {}",
+                cmd,
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+            Ok(_) => (),
+            Err(err) => warn!("error running output pipe `{}`: {:?}", cmd, err),
+        }
+    });
+}
+
+/// Runs `cmd` with the shell without waiting for it, with the tags of `song` in its
+/// environment. Anything it writes to standard error is logged.
+fn run_song_change_hook(cmd: &str, song: &Song) {
+    let tag = |name| song_tag(song, name).unwrap_or_default();
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("MPD_ARTIST", tag("artist"))
+        .env("MPD_TITLE", tag("title"))
+        .env("MPD_ALBUM", tag("album"))
+        .env("MPD_FILE", &song.file)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            warn!("error running song change hook `{}`: {:?}", cmd, err);
+            return;
+        }
+    };
+    let cmd = cmd.to_owned();
+    std::thread::spawn(move || match child.wait_with_output() {
+        Ok(out) if !out.stderr.is_empty() => debug!(
+            "song change hook `{}` exited with {}: {}",
+            cmd,
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ),
+        Ok(_) => (),
+        Err(err) => warn!("error waiting for song change hook `{}`: {:?}", cmd, err),
+    });
+}
+
+/// Wraps `desc` at word boundaries into at most `max_lines` lines of `width` characters. Words
+/// longer than a line, such as CJK text without spaces, are split between characters. If the
+/// text doesn't fit, the last line ends with an ellipsis.
+fn wrap_title(desc: &str, width: usize, max_lines: usize) -> Vec<String> {
+    if width == 0 {
+        return vec![String::new()];
+    }
+    let mut lines: Vec<Vec<char>> = Vec::new();
+    let mut line: Vec<char> = Vec::new();
+    for word in desc.split_whitespace() {
+        let word: Vec<char> = word.chars().collect();
+        if !line.is_empty() {
+            if line.len() + 1 + word.len() <= width {
+                line.push(' ');
+                line.extend(word);
+                continue;
+            }
+            lines.push(std::mem::take(&mut line));
+        }
+        let mut chunks: Vec<&[char]> = word.chunks(width).collect();
+        line = chunks.pop().unwrap_or_default().to_vec();
+        lines.extend(chunks.into_iter().map(<[char]>::to_vec));
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        if let Some(last) = lines.last_mut() {
+            if last.len() == width {
+                last.pop();
+            }
+            last.push('…');
+        }
+    }
+    lines.into_iter().map(String::from_iter).collect()
+}
+
+/// Cuts `s` down to `max_width` columns if it's wider, ending it with an ellipsis.
+fn truncate_width(s: String, max_width: usize) -> String {
+    if s.width() <= max_width {
+        return s;
+    }
+    let mut width = 0;
+    let mut truncated: String = s
+        .chars()
+        .take_while(|c| {
+            width += c.width().unwrap_or(0);
+            // Leave a column for the ellipsis.
+            width < max_width
+        })
+        .collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Looks up a tag by name, including the tags that `Song` keeps in dedicated fields.
+fn song_tag<'a>(song: &'a Song, tag: &str) -> Option<&'a str> {
+    match tag.to_ascii_lowercase().as_str() {
+        "title" => song.title.as_deref(),
+        "artist" => song.artist.as_deref(),
+        "name" => song.name.as_deref(),
+        _ => song
+            .tags
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(tag))
+            .map(|(_, value)| value.as_str()),
+    }
+}
+
+/// Lyrics for a song, as read from its `LYRICS` or `UNSYNCEDLYRICS` tag.
+enum Lyrics {
+    /// Lines with LRC timestamps, sorted by time.
+    Synced(Vec<(Duration, String)>),
+    Unsynced(Vec<String>),
+}
+
+impl Lyrics {
+    fn parse(text: &str) -> Self {
+        let mut synced = Vec::new();
+        for line in text.lines() {
+            let (times, words) = Self::parse_lrc_line(line);
+            synced.extend(times.into_iter().map(|time| (time, words.to_owned())));
+        }
+
+        if synced.is_empty() {
+            Lyrics::Unsynced(text.lines().map(str::to_owned).collect())
+        } else {
+            synced.sort_by_key(|(time, _)| *time);
+            Lyrics::Synced(synced)
+        }
+    }
+
+    /// Splits the leading `[mm:ss.xx]` timestamps off of an LRC line. Metadata tags such as
+    /// `[ar:Artist]` have no timestamp and are returned as-is.
+    fn parse_lrc_line(line: &str) -> (Vec<Duration>, &str) {
+        let mut times = Vec::new();
+        let mut rest = line.trim();
+        while let Some((tag, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+            match Self::parse_lrc_timestamp(tag) {
+                Some(time) => times.push(time),
+                None => break,
+            }
+            rest = after;
+        }
+        (times, rest.trim())
+    }
+
+    fn parse_lrc_timestamp(tag: &str) -> Option<Duration> {
+        let (mins, secs) = tag.split_once(':')?;
+        let mins: u64 = mins.parse().ok()?;
+        let secs = Duration::try_from_secs_f64(secs.parse().ok()?).ok()?;
+        Some(Duration::from_secs(mins * 60) + secs)
+    }
+}
+
+/// Colors and symbols used when rendering; a `None` color leaves the terminal's default color in
+/// place.
+#[derive(Clone, Copy, Debug)]
+struct Theme {
+    border_color: Option<Color>,
+    title_color: Option<Color>,
+    /// Color of error messages shown in place of the art.
+    error_color: Color,
+    /// Color of the [STALE] marker on an outdated status.
+    warning_color: Color,
+    border_set: border::Set,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            border_color: None,
+            title_color: None,
+            error_color: Color::Red,
+            warning_color: Color::Yellow,
+            border_set: border::ROUNDED,
+        }
+    }
+}
+
+impl Theme {
+    const NAMES: [&'static str; 6] = ["dark", "light", "gruvbox", "nord", "solarized", "dracula"];
+
+    /// The bundled theme called `name`.
+    fn from_name(name: &str) -> Option<Theme> {
+        let (border_color, title_color, error_color, warning_color) = match name {
+            "dark" => return Some(Theme::default()),
+            "light" => (
+                Color::DarkGray,
+                Color::Blue,
+                Color::Red,
+                Color::Rgb(175, 95, 0),
+            ),
+            "gruvbox" => (
+                Color::Rgb(146, 131, 116),
+                Color::Rgb(250, 189, 47),
+                Color::Rgb(251, 73, 52),
+                Color::Rgb(254, 128, 25),
+            ),
+            "nord" => (
+                Color::Rgb(76, 86, 106),
+                Color::Rgb(136, 192, 208),
+                Color::Rgb(191, 97, 106),
+                Color::Rgb(235, 203, 139),
+            ),
+            "solarized" => (
+                Color::Rgb(88, 110, 117),
+                Color::Rgb(38, 139, 210),
+                Color::Rgb(220, 50, 47),
+                Color::Rgb(181, 137, 0),
+            ),
+            "dracula" => (
+                Color::Rgb(98, 114, 164),
+                Color::Rgb(189, 147, 249),
+                Color::Rgb(255, 85, 85),
+                Color::Rgb(241, 250, 140),
+            ),
+            _ => return None,
+        };
+        Some(Theme {
+            border_color: Some(border_color),
+            title_color: Some(title_color),
+            error_color,
+            warning_color,
+            border_set: border::ROUNDED,
+        })
+    }
+
+    /// Replaces the colors set in the config file's [colors] section.
+    fn apply_colors(&mut self, colors: &config::Colors) -> Result<()> {
+        let parse = |name: &str, value: &Option<String>| -> Result<Option<Color>> {
+            value
+                .as_deref()
+                .map(|s| {
+                    Color::from_str(s)
+                        .map_err(|_| format!("invalid color for {} in [colors]: {:?}", name, s))
+                })
+                .transpose()
+                .map_err(Into::into)
+        };
+        if let Some(color) = parse("border", &colors.border)? {
+            self.border_color = Some(color);
+        }
+        if let Some(color) = parse("title", &colors.title)? {
+            self.title_color = Some(color);
+        }
+        if let Some(color) = parse("error", &colors.error)? {
+            self.error_color = color;
+        }
+        if let Some(color) = parse("warning", &colors.warning)? {
+            self.warning_color = color;
+        }
+        Ok(())
+    }
+}
+
+fn parse_theme(s: &str) -> std::result::Result<Theme, String> {
+    Theme::from_name(s).ok_or_else(|| {
+        format!(
+            "unknown theme {:?}; expected one of {}",
+            s,
+            Theme::NAMES.join(", ")
+        )
+    })
+}
+
+/// Parses a positive, finite number.
+fn parse_positive_f64(s: &str) -> std::result::Result<f64, String> {
+    let value: f64 = s.parse().map_err(|err| format!("{}", err))?;
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err(format!("{} is not a positive number", value))
+    }
+}
+
+/// Parses a number of seconds that isn't negative and fits in a `Duration`.
+fn parse_secs(s: &str) -> std::result::Result<f64, String> {
+    let value: f64 = s.parse().map_err(|err| format!("{}", err))?;
+    match Duration::try_from_secs_f64(value) {
+        Ok(_) => Ok(value),
+        Err(_) => Err(format!("{} is not a valid number of seconds", value)),
+    }
+}
+
+/// Parses a positive number of seconds that fits in a `Duration`.
+fn parse_positive_secs(s: &str) -> std::result::Result<f64, String> {
+    let value = parse_secs(s)?;
+    if value > 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("{} is not a positive number of seconds", value))
+    }
+}
+
+/// Parses a number between 0.0 and 1.0.
+fn parse_fraction(s: &str) -> std::result::Result<f32, String> {
+    parse_f32_between(s, 0.0, 1.0)
+}
+
+fn parse_f32_between(s: &str, min: f32, max: f32) -> std::result::Result<f32, String> {
+    let value: f32 = s.parse().map_err(|err| format!("{}", err))?;
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!(
+            "{} is not between {:.1} and {:.1}",
+            value, min, max
+        ))
+    }
+}
+
+/// Parses margins to crop given as four colon-separated pixel counts in the order T:R:B:L.
+fn parse_crop(s: &str) -> std::result::Result<Crop, String> {
+    let values = s
+        .split(':')
+        .map(|v| v.parse::<u32>().map_err(|err| format!("{:?}: {}", v, err)))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    match values[..] {
+        [top, right, bottom, left] => Ok(Crop {
+            top,
+            right,
+            bottom,
+            left,
+        }),
+        _ => Err(format!("expected 4 values, got {}", values.len())),
+    }
+}
+
+/// Parses a border set given as eight colon-separated characters in the order
+/// TL:T:TR:R:BR:B:BL:L, or the shorthand "double".
+fn parse_border_set(s: &str) -> std::result::Result<border::Set, String> {
+    if s == "double" {
+        return Ok(border::DOUBLE);
+    }
+
+    let symbols: Vec<&str> = s.split(':').collect();
+    if symbols.len() != 8 {
+        return Err(format!(
+            "expected 8 colon-separated characters, got {}",
+            symbols.len()
+        ));
+    }
+    if let Some(symbol) = symbols.iter().find(|symbol| symbol.chars().count() != 1) {
+        return Err(format!("\"{}\" is not a single character", symbol));
+    }
+
+    // `border::Set` only holds static strings; the set is parsed once at startup, so leaking it is
+    // fine.
+    let symbols: Vec<&'static str> = symbols
+        .into_iter()
+        .map(|symbol| &*Box::leak(symbol.to_owned().into_boxed_str()))
+        .collect();
+    Ok(border::Set {
+        top_left: symbols[0],
+        horizontal_top: symbols[1],
+        top_right: symbols[2],
+        vertical_right: symbols[3],
+        bottom_right: symbols[4],
+        horizontal_bottom: symbols[5],
+        bottom_left: symbols[6],
+        vertical_left: symbols[7],
+    })
+}
+
+#[derive(Default)]
+struct State {
+    viewport_area: Rect,
+    current_song: Option<Song>,
+    art_song: Option<Song>,
+    lyrics_song: Option<Song>,
+    lyrics: Option<Lyrics>,
+    mpd_status: MpdStatus,
+    img_state: ImgState,
+    /// Art of the album played before the current one, for --compare.
+    previous_art: Option<(DynamicImage, Text<'static>)>,
+    /// Art for the next song, converted ahead of time with --preload-next-art.
+    preloaded_art: Option<(Song, ConvertedArt)>,
+    preloading: Option<(Song, JoinHandle<Option<ConvertedArt>>)>,
+    /// When the error in `img_state` was first shown.
+    error_shown_at: Option<Instant>,
+    /// How long fetching the art being converted took, for --metrics-file.
+    fetch_time: Option<Duration>,
+    playlist_tiles: Vec<PlaylistTile>,
+    /// Downloaded art that has not been converted yet because the app is idle.
+    pending_img_bytes: Option<Vec<u8>>,
+    /// The art most recently converted, kept for converting again at a new size.
+    art_bytes: Option<Vec<u8>>,
+    /// What `art_bytes` was last converted with.
+    art_context: Option<ConversionContext>,
+    rating_song: Option<Song>,
+    /// Rating of the current song, from 1 to 5 stars.
+    rating: Option<u8>,
+    /// The song after the current one in the queue, fetched if --show-next-song is set.
+    next_song: Option<Song>,
+    /// The songs in the queue, fetched while the playlist view is shown.
+    queue: Vec<Song>,
+    queue_version: Option<u32>,
+    /// File of the song rated by the user, and its rating, not yet saved to MPD.
+    pending_rating: Option<(String, u8)>,
+    /// Playback commands waiting for the connection to be free.
+    pending_commands: Vec<Action>,
+    /// Picks the hues of the art with --shuffle-art-colors, changed with each song.
+    art_color_seed: u64,
+}
+
+impl State {
+    /// Forgets the current song and its art, so that both are fetched again.
+    fn reset(&mut self) {
+        // A fetch in flight may hold the MPD client, so it's left to finish.
+        if !self.img_state.is_fetching() {
+            self.img_state = ImgState::default();
+        }
+        self.current_song = None;
+        self.art_song = None;
+        self.art_bytes = None;
+        self.art_context = None;
+        self.pending_img_bytes = None;
+        self.preloaded_art = None;
+        self.next_song = None;
+        self.error_shown_at = None;
+    }
+
+    fn dismiss_error(&mut self) {
+        if self.img_state.is_error() {
+            self.img_state.set_idle(None);
+        }
+        self.error_shown_at = None;
+    }
+}
+
+struct App {
+    addr: SocketAddr,
+    connect: ConnectOptions,
+    client: Option<MpdClient>,
+    font: Font,
+    font_aspect: f64,
+    theme: Theme,
+    keymap: Keymap,
+    display: DisplayOptions,
+    conversion: ConversionOptions,
+    background_image: Option<DynamicImage>,
+    /// `background_image` converted for the current terminal size.
+    background: Option<Buffer>,
+    /// Characters the title has scrolled by, including the fraction of the next one, modulo
+    /// the length of the title.
+    scroll_accumulator: f64,
+    last_marquee_update: Instant,
+    /// Columns and rows that zoomed art is scrolled by.
+    scroll_x: i16,
+    scroll_y: i16,
+    state: State,
+    intervals: Intervals,
+    last_activity: Instant,
+    last_status_update: Option<Instant>,
+    last_successful_status: Option<Instant>,
+    /// When the fetching thread took the MPD connection, while it has it. The status can't be
+    /// polled meanwhile, so that time doesn't count toward it going stale.
+    client_lent_at: Option<Instant>,
+    /// Where the fetching thread sends the MPD connection back, while it has it.
+    lent_client: Option<Receiver<MpdClient>>,
+    /// The last --output-pipe command, which the next art isn't piped to until it exits.
+    output_pipe: Option<JoinHandle<()>>,
+    last_art_check: Option<Instant>,
+    last_connection_check: Instant,
+    /// The song --song-change-hook last ran for.
+    hooked_song: Option<Song>,
+    /// Checks on its own thread that MPD still accepts connections, while it's running.
+    connection_check: Option<JoinHandle<bool>>,
+    /// When to fetch the first album art, until it has been fetched.
+    startup_fetch_at: Option<Instant>,
+    /// When the most recent frames were drawn, for the FPS counter.
+    frame_times: VecDeque<Instant>,
+    /// Passes of the event loop not drawn since the last frame, with --frame-skip.
+    skipped_frames: u32,
+    rating_prompt: bool,
+    /// Whether quitting waits for confirmation of --clear-queue-on-exit.
+    clear_queue_prompt: bool,
+    show_help: bool,
+    show_playlist: bool,
+    /// When the keybinding help shown at startup is hidden again.
+    help_dismiss_at: Option<Instant>,
+    /// The state served by --listen, if it is set.
+    snapshot: Option<server::Snapshot>,
+    /// The art being shown as a PNG data URL, kept for the snapshot.
+    art_data_url: Option<String>,
+    /// The bytes and conversion options `art_data_url` was encoded from, so that art that is
+    /// only converted again, e.g. after a resize, isn't encoded again.
+    art_data_url_source: Option<(Vec<u8>, ConversionOptions)>,
+    #[cfg(feature = "visualizer")]
+    visualizer: Option<visualizer::Visualizer>,
+    /// Whether the terminal has focus, as far as it has reported.
+    focused: bool,
+    /// The --ssh-tunnel the connections to MPD go through, closed when the app is dropped.
+    _ssh_tunnel: Option<SshTunnel>,
+    /// When the volume was last changed, to show it for a moment afterwards.
+    volume_changed_at: Option<Instant>,
+    #[cfg(debug_assertions)]
+    show_debug_overlay: bool,
+    /// Number of frames written to the frame dump directory.
+    #[cfg(debug_assertions)]
+    frame_sequence: u64,
+    exit: bool,
+}
+
+const VERT_VIEWPORT_GAP: usize = 3;
+const VERT_BORDER_WIDTH: usize = 1;
+const VERT_PADDING: usize = 1;
+
+const HORIZ_VIEWPORT_GAP: usize = 6;
+const HORIZ_BORDER_WIDTH: usize = 1;
+const HORIZ_PADDING: usize = 2;
+
+/// Rows the art area keeps from the status bar and wrapped title lines, enough for a message
+/// box between the gaps.
+const MIN_ART_HEIGHT: u16 = ((VERT_VIEWPORT_GAP + VERT_BORDER_WIDTH + VERT_PADDING) * 2 + 1) as u16;
+
+/// How many of `wanted` rows can be taken from an area `height` rows tall while leaving
+/// `MIN_ART_HEIGHT` for the art.
+fn rows_beside_art(wanted: u16, height: u16) -> u16 {
+    wanted.min(height.saturating_sub(MIN_ART_HEIGHT))
+}
+
+/// The width, height and vertical padding of a square box for a one-line message within the
+/// gaps of `viewport_area`. If the viewport is too small for that, the box fills it instead.
+fn message_box_size(viewport_area: Rect, font_aspect: f64) -> (u16, u16, usize) {
+    let viewable_width = (viewport_area.width as usize).saturating_sub(HORIZ_VIEWPORT_GAP * 2);
+    let viewable_height = (viewport_area.height as usize).saturating_sub(VERT_VIEWPORT_GAP * 2);
+    let viewport_aspect = viewable_width as f64 * font_aspect / viewable_height as f64;
+    let (width, height, vert_padding) = if viewport_aspect < 1.0 {
+        // Taller than it is wide; use width to form a square.
+        let width = viewable_width as u16;
+        let height = (width as f64 * font_aspect) as u16;
+        let vert_padding = (height as usize).saturating_sub(2 * VERT_BORDER_WIDTH + 1) / 2;
+        (width, height, vert_padding)
+    } else {
+        // Wider than it is tall; Use height to form a square
+        let height = viewable_height as u16;
+        let width = (height as f64 / font_aspect) as u16;
+        let vert_padding = (viewable_height / 2).saturating_sub(VERT_BORDER_WIDTH + 2);
+        (width, height, vert_padding)
+    };
+    // Room for the border, the padding beside the text and one line of it.
+    let min_width = ((HORIZ_BORDER_WIDTH + HORIZ_PADDING) * 2 + 1) as u16;
+    let min_height = (VERT_BORDER_WIDTH * 2 + 1) as u16;
+    if width < min_width || height < min_height {
+        (viewport_area.width, viewport_area.height, 0)
+    } else {
+        (width, height, vert_padding)
+    }
+}
+
+impl App {
+    /// How much less often MPD status is polled while idle.
+    const IDLE_POLL_FACTOR: u32 = 5;
+    /// Number of frames the FPS counter averages over.
+    const FPS_WINDOW: usize = 30;
+    /// Space between the end of the scrolling title and its start.
+    const MARQUEE_GAP: &'static str = "   ";
+    /// Percentage points the volume keys change the volume by.
+    const VOLUME_STEP: i8 = 5;
+    /// How long the volume is shown after changing it.
+    const VOLUME_OVERLAY_DURATION: Duration = Duration::from_secs(2);
+    /// How long the connection check waits for MPD to answer.
+    const CONNECTION_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+    const ALPHABET: &'static str = include_str!("../alphabets/alphabet.txt");
+    const BDF_FILE: &'static str = include_str!("../fonts/bitocra-13.bdf");
+
+    fn load_font(font_height: usize, font_width: usize) -> Font {
+        let alphabet = Self::ALPHABET.chars().collect::<Vec<char>>();
+        let mut font = Font::from_bdf_stream(Self::BDF_FILE.as_bytes(), &alphabet);
+        font.height = font_height;
+        font.width = font_width;
+        font
+    }
+
+    pub fn builder() -> AppBuilder {
+        AppBuilder::default()
+    }
+
+    pub fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
+        self.resize(terminal.get_frame().size());
+
+        self.update_app_state()?;
+        // The first fetch waits until the first frame has been drawn, so it never holds it up.
+        self.startup_fetch_at = Some(Instant::now() + self.intervals.startup_fetch_delay);
+        self.draw(terminal)?;
+        while !self.exit {
+            self.handle_events()?;
+            self.advance_title_marquee();
+            if matches!(self.state.img_state, ImgState::Converting(_))
+                && self.skipped_frames + 1 < self.display.frame_skip
+            {
+                self.skipped_frames += 1;
+                continue;
+            }
+            self.skipped_frames = 0;
+            self.draw(terminal)?;
+        }
+        Ok(())
+    }
+
+    fn draw<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
+        let _frame = terminal.draw(|frame| self.render_frame(frame))?;
+        if self.display.fps_counter {
+            if self.frame_times.len() == Self::FPS_WINDOW {
+                self.frame_times.pop_front();
+            }
+            self.frame_times.push_back(Instant::now());
+        }
+        #[cfg(debug_assertions)]
+        if let Some(dir) = &self.display.frame_dump_dir {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            let path = dir.join(format!("frame_{}_{}.txt", timestamp, self.frame_sequence));
+            self.frame_sequence += 1;
+            if let Err(err) = std::fs::write(&path, buffer_to_text(_frame.buffer)) {
+                warn!("error writing frame to {:?}: {:?}", path, err);
+            }
+        }
+        Ok(())
+    }
+
+    fn render_frame(&self, frame: &mut Frame) {
+        frame.render_widget(self, frame.size())
+    }
+
+    fn handle_events(&mut self) -> Result<()> {
+        loop {
+            if event::poll(self.intervals.event_poll_timeout)? {
+                match event::read()? {
+                    // it's important to check that the event is a key press event as
+                    // crossterm also emits key release and repeat events on Windows.
+                    Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
+                        self.last_activity = Instant::now();
+                        self.handle_key_event(key_event);
+                        break;
+                    }
+                    Event::Resize(width, height) => {
+                        self.resize(Rect::new(0, 0, width, height));
+                        break;
+                    }
+                    Event::FocusLost => self.focused = false,
+                    Event::FocusGained => {
+                        self.focused = true;
+                        // Catch up on any song change missed while unfocused.
+                        self.last_art_check = None;
+                    }
+                    Event::Mouse(mouse_event) if self.display.mouse_scroll_seeks => {
+                        let action = match mouse_event.kind {
+                            MouseEventKind::ScrollUp => Some(Action::SeekBackward),
+                            MouseEventKind::ScrollDown => Some(Action::SeekForward),
+                            _ => None,
+                        };
+                        if let Some(action) = action {
+                            self.last_activity = Instant::now();
+                            self.send_command(action);
+                            break;
+                        }
+                    }
+                    _ => {}
+                };
+            }
+            let status_refresh_period = self.status_refresh_period();
+            if Self::elapsed_since(self.last_status_update, status_refresh_period)
+                >= status_refresh_period
+            {
+                self.update_app_state()?;
+                break;
+            }
+            if self
+                .display
+                .marquee_speed
+                .is_some_and(|speed| self.marquee_offset(speed) != self.marquee_offset(0.0))
+            {
+                break;
+            }
+            if !self.is_idle() && self.state.img_state.advance_animation() {
+                break;
+            }
+            #[cfg(feature = "visualizer")]
+            if !self.is_idle() && self.visualizer.as_ref().is_some_and(|v| v.take_update()) {
+                break;
+            }
+            if self
+                .volume_changed_at
+                .is_some_and(|changed_at| changed_at.elapsed() >= Self::VOLUME_OVERLAY_DURATION)
+            {
+                self.volume_changed_at = None;
+                break;
+            }
+            if let Some(fetch_at) = self.startup_fetch_at {
+                if Instant::now() >= fetch_at {
+                    self.startup_fetch_at = None;
+                    self.update_album_art();
+                    break;
+                }
+                continue;
+            }
+            let art_refresh_period = self.intervals.art_refresh_period;
+            if !self.is_idle()
+                && Self::elapsed_since(self.last_art_check, art_refresh_period)
+                    >= art_refresh_period
+            {
+                self.update_album_art();
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn resize(&mut self, area: Rect) {
+        let viewport_area = self.split_area(area).0;
+        if viewport_area != self.state.viewport_area && self.display.resize_on_window_change {
+            self.reconvert_art();
+        }
+        self.state.viewport_area = viewport_area;
+        self.background = self
+            .background_image
+            .as_ref()
+            .map(|img| self.convert_background(img, area));
+    }
+
+    /// Converts the background image to fill `area`'s width. This uses a faster, lower quality
+    /// conversion than the album art, since it's dimmed and mostly hidden anyway.
+    fn convert_background(&self, img: &DynamicImage, area: Rect) -> Buffer {
+        let rows = convert::img_to_char_rows(
+            &self.font,
+            &LumaImage::from(img),
+            get_converter("intensity"),
+            Some(area.width as usize),
+            0.0,
+            &get_conversion_algorithm("base"),
+        );
+        let text = convert::char_rows_to_terminal_color_string(&rows, img)
+            .into_text()
+            .inspect_err(|err| warn!("error converting ANSI to `Text`: {:?}", err))
+            .unwrap_or_default();
+
+        let mut buf = Buffer::empty(Rect::new(0, 0, text.width() as u16, text.height() as u16));
+        Paragraph::new(text)
+            .style(Style::default().add_modifier(Modifier::DIM))
+            .render(buf.area, &mut buf);
+        buf
+    }
+
+    /// Fills `area` with the background, repeating it if it is smaller than `area`.
+    fn render_background(&self, area: Rect, buf: &mut Buffer) {
+        if let Some(background) = &self.background {
+            render_tiled(background, area, buf);
+        }
+    }
+
+    /// Fills `area` with copies of the album art.
+    fn render_tiled_art(&self, text: &Text, area: Rect, buf: &mut Buffer) {
+        let mut tile = Buffer::empty(Rect::new(0, 0, text.width() as u16, text.height() as u16));
+        Paragraph::new(text.clone()).render(tile.area, &mut tile);
+        render_tiled(&tile, area, buf);
+    }
+
+    fn handle_key_event(&mut self, key_event: KeyEvent) {
+        if self.rating_prompt {
+            self.rating_prompt = false;
+            if let (KeyCode::Char(c @ '1'..='5'), Some(song)) =
+                (key_event.code, &self.state.current_song)
+            {
+                self.state.pending_rating = c.to_digit(10).map(|n| (song.file.clone(), n as u8));
+            }
+            return;
+        }
+        if self.clear_queue_prompt {
+            self.clear_queue_prompt = false;
+            match key_event.code {
+                KeyCode::Char('y' | 'Y') => {
+                    self.clear_queue();
+                    self.exit();
+                }
+                KeyCode::Char('n' | 'N') => self.exit(),
+                _ => (),
+            }
+            return;
+        }
+        // Any key dismisses the error, and still does what it's bound to.
+        self.state.dismiss_error();
+        let Some(action) = self.keymap.action(&key_event) else {
+            return;
+        };
+        match action {
+            Action::Quit if self.display.clear_queue_on_exit => self.clear_queue_prompt = true,
+            Action::Quit => self.exit(),
+            Action::Next
+            | Action::Prev
+            | Action::TogglePause
+            | Action::SeekBackward
+            | Action::SeekForward => self.send_command(action),
+            Action::VolumeUp | Action::VolumeDown => {
+                self.send_command(action);
+                self.volume_changed_at = Some(Instant::now());
+            }
+            Action::ScrollLeft => self.scroll_art(-1, 0),
+            Action::ScrollDown => self.scroll_art(0, 1),
+            Action::ScrollUp => self.scroll_art(0, -1),
+            Action::ScrollRight => self.scroll_art(1, 0),
+            Action::Rate => self.rating_prompt = self.display.enable_stickers,
+            Action::TogglePlaylist => self.show_playlist = !self.show_playlist,
+            Action::ToggleHelp => {
+                self.show_help = !self.show_help;
+                self.help_dismiss_at = None;
+            }
+            Action::HideHelp => self.show_help = false,
+            Action::Export => self.export_art(),
+            Action::Reconvert => {
+                self.state.reset();
+                // Fetch the song and its art again right away.
+                self.last_status_update = None;
+                self.last_art_check = None;
+            }
+            #[cfg(debug_assertions)]
+            Action::ToggleDebug => self.show_debug_overlay = !self.show_debug_overlay,
+            #[cfg(not(debug_assertions))]
+            Action::ToggleDebug => {}
+        }
+    }
+
+    /// Converts the current art again, e.g. to fit a new terminal size.
+    fn reconvert_art(&mut self) {
+        if let Some(bytes) = &self.state.art_bytes {
+            info!("converting album art again");
+            self.state.pending_img_bytes = Some(bytes.clone());
+            // Start converting right away rather than at the next status refresh.
+            self.last_status_update = None;
+        }
+    }
+
+    /// Sends a playback command to MPD, or queues it if the connection is in use by an album
+    /// art fetch.
+    fn send_command(&mut self, action: Action) {
+        match self.client.as_mut() {
+            Some(client) => Self::run_command(client, action, self.display.seek_step),
+            None => self.state.pending_commands.push(action),
+        }
+        // Refresh the status right away, to show the effect of the command.
+        self.last_status_update = None;
+    }
+
+    fn run_command(client: &mut MpdClient, action: Action, seek_step: Duration) {
+        let result = match action {
+            Action::Next => client.next(),
+            Action::Prev => client.prev(),
+            Action::TogglePause => client.toggle_pause(),
+            Action::VolumeUp | Action::VolumeDown => client.status().and_then(|status| {
+                if status.volume < 0 {
+                    // MPD has no mixer to change the volume with.
+                    return Ok(());
+                }
+                let step = match action {
+                    Action::VolumeUp => Self::VOLUME_STEP,
+                    _ => -Self::VOLUME_STEP,
+                };
+                client.volume((status.volume + step).clamp(0, 100))
+            }),
+            Action::SeekBackward | Action::SeekForward => client.status().and_then(|status| {
+                let Some(elapsed) = status.elapsed.filter(|_| status.state != MpdState::Stop)
+                else {
+                    return Ok(());
+                };
+                let position = match action {
+                    Action::SeekForward => elapsed + seek_step,
+                    _ => elapsed.saturating_sub(seek_step),
+                };
+                client.rewind(position)
+            }),
+            _ => unreachable!("{:?} is not an MPD command", action),
+        };
+        if let Err(err) = result {
+            warn!("error sending {:?} to MPD: {:?}", action, err);
+        }
+    }
+
+    /// Scrolls zoomed art, keeping at least one row and column of it visible.
+    fn scroll_art(&mut self, dx: i16, dy: i16) {
+        if self.conversion.zoom <= 1.0 {
+            return;
+        }
+        let Some(text) = self.state.img_state.text() else {
+            return;
+        };
+        let max_x = (text.width() as i16 - 1).max(0);
+        let max_y = (text.height() as i16 - 1).max(0);
+        self.scroll_x = (self.scroll_x + dx).clamp(0, max_x);
+        self.scroll_y = (self.scroll_y + dy).clamp(0, max_y);
+    }
+
+    /// The directory that identifies the album of `song`, `--album-dir-depth` levels up from
+    /// the song's own directory.
+    fn album_dir<'a>(&self, song: &'a Song) -> Option<&'a Path> {
+        Path::new(&song.file)
+            .parent()
+            .and_then(|dir| dir.ancestors().take(self.display.album_dir_depth).last())
+    }
+
+    fn songs_in_same_dir(&self, song0: &Song, song1: &Song) -> bool {
+        let dir0 = self.album_dir(song0);
+        let dir1 = self.album_dir(song1);
+        debug!("songs_in_same_dir: {:?}, {:?}", dir0, dir1);
+        dir0 == dir1
+    }
+
+    /// Replaces the MPD connection with a new one, forgetting everything learned over the old
+    /// one. The old connection is kept if connecting fails, to be retried on the next failure.
+    fn reconnect(
+        addr: SocketAddr,
+        options: &ConnectOptions,
+        client: &mut MpdClient,
+        state: &mut State,
+    ) {
+        state.reset();
+        match connect_mpd(addr, options) {
+            Ok(new_client) => *client = new_client,
+            Err(err) => warn!("error reconnecting to MPD: {:?}", err),
+        }
+    }
+
+    fn update_app_state(&mut self) -> Result<()> {
+        info!("updating app state; img_state: {}", self.state.img_state);
+        if self
+            .help_dismiss_at
+            .is_some_and(|dismiss_at| Instant::now() >= dismiss_at)
+        {
+            self.show_help = false;
+            self.help_dismiss_at = None;
+        }
+        if let (Some(shown_at), Some(duration)) = (
+            self.state.error_shown_at,
+            self.display.error_display_duration,
+        ) {
+            if shown_at.elapsed() >= duration {
+                self.state.dismiss_error();
+            }
+        }
+        let mut new_img_bytes = None;
+        // Checked before taking the connection back, which the thread sends before finishing.
+        let fetched = self.state.img_state.try_finish_fetching();
+        if let Some(client) = self.lent_client.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            self.lent_client = None;
+            self.resume_status_clock();
+            self.client = Some(client);
+        }
+        match fetched {
+            None => (),
+            Some(Ok((new_bytes, elapsed))) => {
+                self.state.img_state.set_idle(None);
+                self.state.fetch_time = Some(elapsed);
+                new_img_bytes = new_bytes;
+            }
+            Some(Err(err)) => {
+                warn!("error joining fetching thread: {:?}", err);
+                self.state.img_state.set_idle(None);
+                self.state.reset();
+                if self.client.is_none() {
+                    // It panicked with the connection, which is connected again below.
+                    self.lent_client = None;
+                    self.resume_status_clock();
+                }
+            }
+        }
+        if self.client.is_none() {
+            if self.state.img_state.is_fetching() {
+                // Blocked waiting for MPD's art
+                info!("still fetching; img_state: {}", self.state.img_state);
+                return Ok(());
+            }
+            match connect_mpd(self.addr, &self.connect) {
+                Ok(client) => self.client = Some(client),
+                Err(err) => {
+                    // Tried again at the next status refresh, while the status goes stale.
+                    warn!("error reconnecting to MPD: {:?}", err);
+                    self.last_status_update = Some(Instant::now());
+                    return Ok(());
+                }
+            }
+        }
+
+        let client = self.client.as_mut().unwrap();
+        if self.connection_check.is_none()
+            && self
+                .intervals
+                .connection_check_period
+                .is_some_and(|period| self.last_connection_check.elapsed() >= period)
+        {
+            self.last_connection_check = Instant::now();
+            // Connecting can take up to the timeout, which would hold up drawing.
+            let addr = self.addr;
+            let connect = self.connect.clone();
+            self.connection_check = Some(std::thread::spawn(move || {
+                check_mpd_alive(addr, &connect, Self::CONNECTION_CHECK_TIMEOUT)
+            }));
+        }
+        if self
+            .connection_check
+            .as_ref()
+            .is_some_and(|jh| jh.is_finished())
+        {
+            let alive = self
+                .connection_check
+                .take()
+                .unwrap()
+                .join()
+                .inspect_err(|err| warn!("error joining connection check thread: {:?}", err))
+                .unwrap_or(true);
+            if !alive {
+                Self::reconnect(self.addr, &self.connect, client, &mut self.state);
+            }
+        }
+        for action in std::mem::take(&mut self.state.pending_commands) {
+            Self::run_command(client, action, self.display.seek_step);
+        }
+        match client
+            .status()
+            .and_then(|status| Ok((status, client.currentsong()?)))
+        {
+            Ok((status, song)) => {
+                if song != self.state.current_song {
+                    self.state.art_color_seed = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_or(0, |time| time.as_nanos() as u64);
+                    self.scroll_accumulator = 0.0;
+                    self.last_marquee_update = Instant::now();
+                }
+                // Compared with the last song the hook ran for rather than `current_song`,
+                // which is forgotten on reconnecting or with ctrl+r.
+                if let (Some(cmd), Some(song)) = (&self.display.song_change_hook, &song) {
+                    if self.hooked_song.as_ref() != Some(song) {
+                        run_song_change_hook(cmd, song);
+                        self.hooked_song = Some(song.clone());
+                    }
+                }
+                self.state.mpd_status = status;
+                self.state.current_song = song;
+                self.last_successful_status = Some(Instant::now());
+            }
+            Err(mpd::error::Error::Io(err)) => {
+                warn!("lost connection to MPD: {:?}", err);
+                Self::reconnect(self.addr, &self.connect, client, &mut self.state);
+            }
+            Err(err) => warn!("error polling MPD status: {:?}", err),
+        }
+
+        if self.display.scroll_lyrics && self.state.lyrics_song != self.state.current_song {
+            self.state.lyrics = self
+                .state
+                .current_song
+                .as_ref()
+                .and_then(|song| Self::fetch_lyrics(client, song));
+            self.state.lyrics_song.clone_from(&self.state.current_song);
+        }
+        if self.display.enable_stickers {
+            Self::update_rating(client, &mut self.state);
+        }
+        if self.display.show_next_song {
+            Self::update_next_song(client, &mut self.state);
+        }
+        if self.show_playlist
+            && self.state.queue_version != Some(self.state.mpd_status.queue_version)
+        {
+            match client.queue() {
+                Ok(queue) => {
+                    self.state.queue = queue;
+                    self.state.queue_version = Some(self.state.mpd_status.queue_version);
+                }
+                Err(err) => warn!("error fetching queue: {:?}", err),
+            }
+        }
+        self.last_status_update = Some(Instant::now());
+        self.publish_state();
+
+        for tile in &mut self.state.playlist_tiles {
+            tile.art.try_finish();
+        }
+        if let Some((_, jh)) = &self.state.preloading {
+            if jh.is_finished() {
+                let (song, jh) = self.state.preloading.take().unwrap();
+                self.state.preloaded_art = jh
+                    .join()
+                    .inspect_err(|err| warn!("error joining preloading thread: {:?}", err))
+                    .ok()
+                    .flatten()
+                    .map(|art| (song, art));
+            }
+        }
+
+        if new_img_bytes.is_some() {
+            self.state.pending_img_bytes = new_img_bytes;
+        }
+        if self.state.img_state.is_converting() {
+            if let Some(convert_time) = self.state.img_state.try_finish_converting() {
+                self.show_new_art();
+                let fetch_time = self.state.fetch_time.take();
+                // Art converted again without fetching is the same art.
+                if fetch_time.is_some() {
+                    self.save_snapshot();
+                }
+                self.write_metrics(fetch_time, Some(convert_time), false);
+                info!("converting done; img_state: {}", self.state.img_state);
+            } else if self.state.img_state.is_error() {
+                self.state.error_shown_at = Some(Instant::now());
+            }
+        } else if !self.is_idle() && !self.state.img_state.is_fetching() {
+            if let Some(bytes) = self.state.pending_img_bytes.take() {
+                let conv_ctx = self.conversion_context(self.state.viewport_area);
+                // The viewport can be resized and then put back before the art is converted
+                // again, which would give the same text as is still shown.
+                if self.state.img_state.image().is_some()
+                    && self.state.art_bytes.as_ref() == Some(&bytes)
+                    && self.state.art_context.as_ref() == Some(&conv_ctx)
+                {
+                    info!("skipping conversion; the art is already converted for this viewport");
+                } else {
+                    self.state.art_bytes = Some(bytes.clone());
+                    self.state.art_context = Some(conv_ctx.clone());
+                    self.state.img_state.start_converting(bytes, conv_ctx);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resets the scroll position for newly converted art, and pipes it to --output-pipe.
+    fn show_new_art(&mut self) {
+        if self.snapshot.is_some() {
+            let source = self
+                .state
+                .art_bytes
+                .clone()
+                .map(|bytes| (bytes, self.conversion.clone()));
+            if source.is_none() || source != self.art_data_url_source {
+                self.art_data_url = self.state.img_state.image().and_then(|img| {
+                    let mut png = Vec::new();
+                    img.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                        .inspect_err(|err| warn!("error encoding album art as PNG: {:?}", err))
+                        .ok()?;
+                    Some(format!("data:image/png;base64,{}", server::base64(&png)))
+                });
+                self.art_data_url_source = source;
+            }
+            self.publish_state();
+        }
+        if let (Some(cmd), Some(text)) = (&self.display.output_pipe, self.state.img_state.text()) {
+            let output = text_to_ansi(text);
+            let output = if self.display.no_ansi_output {
+                strip_ansi(&output)
+            } else {
+                output
+            };
+            if self
+                .output_pipe
+                .as_ref()
+                .is_some_and(|jh| !jh.is_finished())
+            {
+                warn!(
+                    "output pipe `{}` is still running; not piping the new art",
+                    cmd
+                );
+            } else {
+                self.output_pipe = Some(pipe_output(cmd.clone(), output));
+            }
+        }
+        self.scroll_x = 0;
+        self.scroll_y = 0;
+    }
+
+    /// Compares the song whose art is displayed with the current song, and starts fetching new
+    /// art if it has changed.
+    fn update_album_art(&mut self) {
+        self.last_art_check = Some(Instant::now());
+        if self.client.is_none() || self.state.img_state.is_fetching() {
+            // A fetch is already in flight, or MPD is being reconnected to; check again later.
+            return;
+        }
+        if self.display.compact_mode || (self.display.pause_fetch_when_hidden && !self.focused) {
+            return;
+        }
+        if let Some(max_tiles) = self.display.playlist_art_max {
+            self.update_playlist_art(max_tiles);
+            return;
+        }
+        if let (Some(min), Some((_, total))) =
+            (self.display.skip_short_songs, self.state.mpd_status.time)
+        {
+            if total < min {
+                // Leave art_song alone, so the next longer song is compared with the art shown.
+                debug!("skipping art fetch for a {:?} song", total);
+                return;
+            }
+        }
+
+        let album_art_changed = match (&self.state.art_song, &self.state.current_song) {
+            (None, None) => false,
+            (Some(song0), Some(song1)) if song0 == song1 => false,
+            (Some(song0), Some(song1)) => !self.songs_in_same_dir(song0, song1),
+            _ => true,
+        };
+
+        self.state.art_song.clone_from(&self.state.current_song);
+        if album_art_changed {
+            debug!("album_art_changed!");
+            if self.display.compare {
+                match std::mem::take(&mut self.state.img_state) {
+                    ImgState::Idle(Some(art)) => self.state.previous_art = Some(art),
+                    ImgState::Animated(img, animation) => {
+                        self.state.previous_art = Some((img, animation.current_frame().clone()))
+                    }
+                    _ => (),
+                }
+            }
+            match self.take_preloaded_art() {
+                Some(art) => {
+                    // The viewport may have been resized since the art was preloaded, in which
+                    // case it's shown as it is until it's converted again for the new size.
+                    if art.context != self.conversion_context(self.state.viewport_area) {
+                        info!("preloaded art is for another viewport; converting it again");
+                        self.state.pending_img_bytes = Some(art.bytes.clone());
+                    }
+                    self.state.art_bytes = Some(art.bytes);
+                    self.state.art_context = Some(art.context);
+                    self.state.img_state.set_idle(Some((art.img, art.text)));
+                    self.show_new_art();
+                    self.save_snapshot();
+                    self.write_metrics(None, None, true);
+                }
+                None => {
+                    self.client_lent_at = Some(Instant::now());
+                    self.lent_client = Some(self.state.img_state.start_fetching(
+                        self.client.take().unwrap(),
+                        self.state.current_song.clone(),
+                        self.art_sources(),
+                    ))
+                }
+            }
+            info!("album art changed; img_state: {}", self.state.img_state);
+        } else if self.display.preload_next_art && self.state.img_state.text().is_some() {
+            self.preload_next_art();
+        }
+    }
+
+    /// Saves the art being shown to the current directory, named after its album's directory.
+    fn export_art(&self) {
+        let Some(img) = self.state.img_state.image() else {
+            return;
+        };
+        let name = self
+            .state
+            .art_song
+            .as_ref()
+            .and_then(|song| self.album_dir(song))
+            .and_then(|dir| dir.file_name())
+            .map_or("album-art".into(), |name| name.to_string_lossy());
+        let format = self.display.export_format;
+        let path = PathBuf::from(format!(
+            "{}.{}",
+            name,
+            format.image_format().extensions_str()[0]
+        ));
+        match export_image(img, &path, format, self.display.export_jpeg_quality) {
+            Ok(()) => info!("exported album art to {}", path.display()),
+            Err(err) => warn!("error exporting album art to {}: {:?}", path.display(), err),
+        }
+    }
+
+    /// Replaces the state served by --listen, if it is set.
+    fn publish_state(&self) {
+        let Some(snapshot) = &self.snapshot else {
+            return;
+        };
+        let status = &self.state.mpd_status;
+        let state = StateSnapshot {
+            song: self.state.current_song.as_ref().map(|song| {
+                let (artist, title) = self.song_artist_title(song);
+                SongSnapshot {
+                    file: &song.file,
+                    artist,
+                    title,
+                    album: song_tag(song, "album"),
+                }
+            }),
+            state: match status.state {
+                MpdState::Stop => "stop",
+                MpdState::Play => "play",
+                MpdState::Pause => "pause",
+            },
+            elapsed_secs: status.elapsed.map(|elapsed| elapsed.as_secs_f64()),
+            duration_secs: status.duration.map(|duration| duration.as_secs_f64()),
+            volume: status.volume,
+            viewport_width: self.state.viewport_area.width,
+            viewport_height: self.state.viewport_area.height,
+        };
+        // The album art is published as a PNG data URL alongside, since it changes less often.
+        let art = self
+            .state
+            .img_state
+            .image()
+            .and(self.art_data_url.as_deref());
+        if let Err(err) = snapshot.publish(&state, art) {
+            warn!("error serializing state: {:?}", err);
+        }
+    }
+
+    /// Appends a line describing the art being shown to the --metrics-file, if there is one.
+    fn write_metrics(
+        &self,
+        fetch_time: Option<Duration>,
+        convert_time: Option<Duration>,
+        cache_hit: bool,
+    ) {
+        let Some(path) = &self.display.metrics_file else {
+            return;
+        };
+        let (Some(img), Some(text)) = (self.state.img_state.image(), self.state.img_state.text())
+        else {
+            return;
+        };
+        let metric = Metric {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            song_file: self
+                .state
+                .art_song
+                .as_ref()
+                .map_or("", |song| song.file.as_str()),
+            fetch_ms: fetch_time.map(|time| time.as_millis()),
+            convert_ms: convert_time.map(|time| time.as_millis()),
+            image_w: img.width(),
+            image_h: img.height(),
+            ascii_w: text.width(),
+            ascii_h: text.height(),
+            cache_hit,
+        };
+        if let Err(err) = write_metric(path, &metric) {
+            warn!("error writing metrics to {}: {}", path.display(), err);
+        }
+    }
+
+    /// Saves the art being shown as a PNG in the --snapshot-on-change directory, then deletes
+    /// the oldest snapshots beyond --snapshot-max-files.
+    fn save_snapshot(&self) {
+        let (Some(dir), Some(img), Some(song)) = (
+            &self.display.snapshot_dir,
+            self.state.img_state.image(),
+            &self.state.art_song,
+        ) else {
+            return;
+        };
+        let (artist, title) = self.song_artist_title(song);
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = dir.join(format!(
+            "{}-{}-{}.png",
+            iso8601_basic(timestamp),
+            sanitize_file_name(artist.unwrap_or("Unknown artist")),
+            sanitize_file_name(title.unwrap_or("Unknown song"))
+        ));
+        let result = std::fs::create_dir_all(dir)
+            .map_err(Into::into)
+            .and_then(|()| img.save_with_format(&path, ImageFormat::Png));
+        match result {
+            Ok(()) => info!("saved album art snapshot to {}", path.display()),
+            Err(err) => {
+                warn!("error saving snapshot to {}: {:?}", path.display(), err);
+                return;
+            }
+        }
+        if let Err(err) = remove_old_snapshots(dir, self.display.snapshot_max_files) {
+            warn!(
+                "error removing old snapshots from {}: {:?}",
+                dir.display(),
+                err
+            );
+        }
+    }
+
+    /// Takes the preloaded art, if it's for the current song's album.
+    fn take_preloaded_art(&mut self) -> Option<ConvertedArt> {
+        let (song, art) = self.state.preloaded_art.take()?;
+        let current_song = self.state.current_song.as_ref()?;
+        self.songs_in_same_dir(&song, current_song).then_some(art)
+    }
+
+    /// Starts fetching and converting the next song's art on its own connection, unless that
+    /// has already been done or the next song is on the current album.
+    fn preload_next_art(&mut self) {
+        let Some(next) = self.state.mpd_status.nextsong else {
+            return;
+        };
+        let is_next = |song: &Song| song.place.is_some_and(|place| place.id == next.id);
+        if self
+            .state
+            .preloading
+            .as_ref()
+            .is_some_and(|(song, _)| is_next(song))
+            || self
+                .state
+                .preloaded_art
+                .as_ref()
+                .is_some_and(|(song, _)| is_next(song))
+        {
+            return;
+        }
+        let song = match self.client.as_mut().unwrap().playlistid(next.id) {
+            Ok(Some(song)) => song,
+            Ok(None) => return,
+            Err(err) => {
+                warn!("error fetching the next song: {:?}", err);
+                return;
+            }
+        };
+        if self
+            .state
+            .current_song
+            .as_ref()
+            .is_some_and(|current_song| self.songs_in_same_dir(current_song, &song))
+        {
+            return;
+        }
+
+        info!("preloading art for {:?}", song.file);
+        let addr = self.addr;
+        let connect = self.connect.clone();
+        let conv_ctx = self.conversion_context(self.state.viewport_area);
+        let sources = self.art_sources();
+        let next_song = song.clone();
+        let jh = std::thread::spawn(move || -> Option<ConvertedArt> {
+            let mut client = connect_mpd(addr, &connect)
+                .inspect_err(|err| warn!("error connecting to MPD: {:?}", err))
+                .ok()?;
+            let bytes = fetch_art(&mut client, &next_song, &sources)?;
+            conv_ctx.check_memory(&bytes).ok()?;
+            let (img, text) = conv_ctx.convert(bytes.clone())?;
+            Some(ConvertedArt {
+                img,
+                text,
+                bytes,
+                context: conv_ctx,
+            })
+        });
+        self.state.preloading = Some((song, jh));
+    }
+
+    /// Fetches the current song's art and prints how long converting it to ASCII
+    /// `BENCHMARK_WIDTH` columns wide takes with each converter and algorithm, fastest first.
+    fn conversion_benchmark(&mut self) -> Result<()> {
+        const CONVERTERS: &[&str] = &[
+            "direction-and-intensity",
+            "direction",
+            "intensity",
+            "dot",
+            "jaccard",
+            "occlusion",
+            "color",
+            "clear",
+        ];
+        const ALGORITHMS: &[&str] = &["edge-augmented", "edge", "base", "two-pass"];
+        const BENCHMARK_WIDTH: usize = 80;
+
+        let sources = self.art_sources();
+        let client = self.client.as_mut().unwrap();
+        let song = client.currentsong()?.ok_or("no song is playing")?;
+        let bytes = fetch_art(client, &song, &sources)
+            .ok_or_else(|| format!("no album art found for \"{}\"", song.file))?;
+        let dyn_img = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()?
+            .decode()?;
+        let luma = LumaImage::from(&dyn_img);
+        println!(
+            "converting {} x {} art for \"{}\" to {} columns",
+            dyn_img.width(),
+            dyn_img.height(),
+            song.file,
+            BENCHMARK_WIDTH
+        );
+
+        let mut timings = Vec::new();
+        for converter in CONVERTERS {
+            for algorithm in ALGORITHMS {
+                let start = Instant::now();
+                convert::img_to_char_rows(
+                    &self.font,
+                    &luma,
+                    get_converter(converter),
+                    Some(BENCHMARK_WIDTH),
+                    0.0,
+                    &get_conversion_algorithm(algorithm),
+                );
+                timings.push((start.elapsed(), converter, algorithm));
+            }
+        }
+        timings.sort();
+
+        println!("{:<25} {:<15} {:>10}", "CONVERTER", "ALGORITHM", "TIME");
+        for (elapsed, converter, algorithm) in timings {
+            println!(
+                "{:<25} {:<15} {:>10}",
+                converter,
+                algorithm,
+                format!("{:.1?}", elapsed)
+            );
+        }
+        Ok(())
+    }
+
+    /// Fetches the current song's art and prints it in `format`, converted to fit the terminal.
+    fn print_once(&mut self, format: OutputFormat) -> Result<()> {
+        let sources = self.art_sources();
+        let client = self.client.as_mut().unwrap();
+        let song = client.currentsong()?.ok_or("no song is playing")?;
+        let bytes = fetch_art(client, &song, &sources)
+            .ok_or_else(|| format!("no album art found for \"{}\"", song.file))?;
+        let (width, height) = ratatui::crossterm::terminal::size().unwrap_or((80, 24));
+        let conv_ctx = self.conversion_context(Rect::new(0, 0, width, height));
+        conv_ctx.check_memory(&bytes)?;
+        let (_, text) = conv_ctx
+            .convert(bytes)
+            .ok_or("error converting the album art")?;
+        let output = match format {
+            OutputFormat::Ansi if self.display.no_ansi_output => strip_ansi(&text_to_ansi(&text)),
+            OutputFormat::Ansi => text_to_ansi(&text),
+            OutputFormat::Html => text_to_html(&text),
+            OutputFormat::Rtf => text_to_rtf(&text),
+        };
+        print!("{}", output);
+        if format == OutputFormat::Ansi {
+            println!();
+        }
+        Ok(())
+    }
+
+    fn art_sources(&self) -> ArtSources {
+        ArtSources {
+            hook: self.display.art_fetch_hook.clone(),
+            priority: self.display.art_priority.clone(),
+            max_bytes: self.display.album_art_max_fetch_bytes,
+            lastfm_api_key: self.display.lastfm_api_key.clone(),
+            musicbrainz_art: self.display.musicbrainz_art,
+            discogs_token: self.display.discogs_token.clone(),
+            spotify: self.display.spotify.clone(),
+        }
+    }
+
+    fn conversion_context(&self, area: Rect) -> ConversionContext {
+        ConversionContext {
+            area,
+            font: self.font.clone(),
+            font_aspect: self.font_aspect,
+            options: self.conversion.clone(),
+            tile: false,
+        }
+    }
+
+    /// Starts fetching art for the first `max_tiles` albums in the queue, if they have changed.
+    fn update_playlist_art(&mut self, max_tiles: usize) {
+        let queue = match self.client.as_mut().unwrap().queue() {
+            Ok(queue) => queue,
+            Err(err) => {
+                warn!("error fetching queue: {:?}", err);
+                return;
+            }
+        };
+
+        let mut albums: Vec<Song> = Vec::new();
+        for song in queue {
+            if albums.len() == max_tiles {
+                break;
+            }
+            if !albums
+                .iter()
+                .any(|album| self.songs_in_same_dir(album, &song))
+            {
+                albums.push(song);
+            }
+        }
+
+        let tiles = &self.state.playlist_tiles;
+        if albums.len() == tiles.len()
+            && albums
+                .iter()
+                .zip(tiles)
+                .all(|(song, tile)| self.songs_in_same_dir(song, &tile.song))
+        {
+            return;
+        }
+
+        info!("fetching playlist art for {} albums", albums.len());
+        let inner_area = Block::bordered().inner(self.state.viewport_area);
+        let tile_areas = Self::grid_areas(inner_area, albums.len());
+        self.state.playlist_tiles = albums
+            .into_iter()
+            .zip(tile_areas)
+            .map(|(song, area)| {
+                let addr = self.addr;
+                let connect = self.connect.clone();
+                let conv_ctx = ConversionContext {
+                    tile: true,
+                    ..self.conversion_context(area)
+                };
+                let sources = self.art_sources();
+                let tile_song = song.clone();
+                let jh = std::thread::spawn(move || -> Option<Text<'static>> {
+                    // Each tile gets its own connection so that the art is fetched in parallel.
+                    let mut client = connect_mpd(addr, &connect)
+                        .inspect_err(|err| warn!("error connecting to MPD: {:?}", err))
+                        .ok()?;
+                    let bytes = fetch_art(&mut client, &tile_song, &sources)?;
+                    conv_ctx.convert(bytes).map(|(_, text)| text)
+                });
+                PlaylistTile {
+                    song,
+                    art: TileArt::Loading(jh),
+                }
+            })
+            .collect();
+    }
+
+    /// Lays out `n` tiles in a grid that is as close to square as possible.
+    fn grid_areas(area: Rect, n: usize) -> Vec<Rect> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let cols = (n as f64).sqrt().ceil() as usize;
+        let rows = n.div_ceil(cols);
+        Layout::vertical(vec![Constraint::Ratio(1, rows as u32); rows])
+            .split(area)
+            .iter()
+            .flat_map(|row| {
+                Layout::horizontal(vec![Constraint::Ratio(1, cols as u32); cols])
+                    .split(*row)
+                    .to_vec()
+            })
+            .take(n)
+            .collect()
+    }
+
+    fn fetch_lyrics(client: &mut MpdClient, song: &Song) -> Option<Lyrics> {
+        // The response must be read in full, or it would be mistaken for the reply to the
+        // next command.
+        let comments = client
+            .readcomments(song)
+            .and_then(|comments| comments.collect::<mpd::error::Result<Vec<_>>>())
+            .inspect_err(|err| warn!("error reading comments for \"{}\": {:?}", song.file, err))
+            .ok()?;
+        let find = |tag: &str| {
+            comments
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(tag))
+                .map(|(_, value)| Lyrics::parse(value))
+        };
+        find("LYRICS").or_else(|| find("UNSYNCEDLYRICS"))
+    }
+
+    /// Fetches the next song in the queue if it has changed.
+    fn update_next_song(client: &mut MpdClient, state: &mut State) {
+        let next_id = state.mpd_status.nextsong.map(|place| place.id);
+        let fetched_id = state
+            .next_song
+            .as_ref()
+            .and_then(|song| song.place)
+            .map(|place| place.id);
+        if next_id == fetched_id {
+            return;
+        }
+        state.next_song = match next_id {
+            None => None,
+            Some(id) => client
+                .playlistid(id)
+                .inspect_err(|err| warn!("error fetching the next song: {:?}", err))
+                .ok()
+                .flatten(),
+        };
+    }
+
+    /// Saves any rating the user has entered, and reads the rating of the current song if it
+    /// has changed.
+    fn update_rating(client: &mut MpdClient, state: &mut State) {
+        // The rating is saved for the song it was entered for, even if another is playing now.
+        if let Some((file, rating)) = state.pending_rating.take() {
+            match client.set_sticker("song", &file, "rating", &rating.to_string()) {
+                Ok(()) => {
+                    if state
+                        .rating_song
+                        .as_ref()
+                        .is_some_and(|song| song.file == file)
+                    {
+                        state.rating = Some(rating);
+                    }
+                }
+                Err(err) => warn!("error saving rating for \"{}\": {:?}", file, err),
+            }
+        }
+        let Some(song) = &state.current_song else {
+            state.rating = None;
+            return;
+        };
+        if state.rating_song.as_ref() != Some(song) {
+            // Songs that have never been rated have no sticker, which MPD reports as an error.
+            state.rating = client
+                .sticker("song", &song.file, "rating")
+                .inspect_err(|err| debug!("no rating for \"{}\": {:?}", song.file, err))
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|rating| (1..=5).contains(rating));
+            state.rating_song = Some(song.clone());
+        }
+    }
+
+    fn elapsed_since(last: Option<Instant>, period: Duration) -> Duration {
+        match last {
+            None => period,
+            Some(last) => Instant::now().duration_since(last),
+        }
+    }
+
+    fn song_desc(&self) -> String {
+        let desc = self
+            .state
+            .current_song
+            .as_ref()
+            .map(|song| self.artist_title_desc(song))
+            .unwrap_or("No song playing".to_owned());
+        match self.display.max_title_length {
+            Some(max_width) => truncate_width(desc, max_width),
+            None => desc,
+        }
+    }
+
+    fn next_song_desc(&self) -> String {
+        match &self.state.next_song {
+            Some(song) => format!("Next: {}", self.artist_title_desc(song)),
+            None => "Next: (end of queue)".to_owned(),
+        }
+    }
+
+    /// "Artist - Title" for `song`, normalized for display.
+    fn artist_title_desc(&self, song: &Song) -> String {
+        let (artist, title) = self.song_artist_title(song);
+        let desc = format!(
+            "{} - {}",
+            artist.unwrap_or("Unknown artist"),
+            title.unwrap_or("Unknown song")
+        );
+        normalize(&desc, self.display.unicode_normalize)
+    }
+
+    /// Returns the artist and title of `song`. Internet radio streams usually have no artist
+    /// tag, and instead put the ICY metadata of the current track, "Artist - Title", in the
+    /// stream title tag.
+    fn song_artist_title<'a>(&self, song: &'a Song) -> (Option<&'a str>, Option<&'a str>) {
+        if song.artist.is_none() {
+            if let Some(stream_title) = song_tag(song, &self.display.stream_title_tag) {
+                return match stream_title.split_once(" - ") {
+                    Some((artist, title)) => (Some(artist.trim()), Some(title.trim())),
+                    None => (song.name.as_deref(), Some(stream_title)),
+                };
+            }
+        }
+        (song.artist.as_deref(), song.title.as_deref())
+    }
+
+    /// The whole number of characters the title will have scrolled by if it's scrolled at
+    /// `speed` since the last update.
+    fn marquee_offset(&self, speed: f64) -> usize {
+        (self.scroll_accumulator + speed * self.last_marquee_update.elapsed().as_secs_f64()).floor()
+            as usize
+    }
+
+    fn advance_title_marquee(&mut self) {
+        let now = Instant::now();
+        let dt = now - self.last_marquee_update;
+        self.last_marquee_update = now;
+        let Some(speed) = self.display.marquee_speed else {
+            return;
+        };
+        let len = self.song_desc().chars().count() + Self::MARQUEE_GAP.len();
+        self.scroll_accumulator = (self.scroll_accumulator + speed * dt.as_secs_f64()) % len as f64;
+    }
+
+    /// Scrolls `desc` through a window `width` characters wide if the title marquee is enabled
+    /// and `desc` doesn't fit.
+    fn marquee(&self, desc: String, width: usize) -> String {
+        if self.display.marquee_speed.is_none() || desc.chars().count() <= width {
+            return desc;
+        }
+        let chars: Vec<char> = desc.chars().chain(Self::MARQUEE_GAP.chars()).collect();
+        chars
+            .iter()
+            .cycle()
+            .skip(self.scroll_accumulator.floor() as usize % chars.len())
+            .take(width)
+            .collect()
+    }
+
+    fn fmt_duration(d: &Duration) -> String {
+        let s = d.as_secs();
+        format!("{:02}:{:02}", s / 60, s % 60)
     }
 
     fn status_desc(&self) -> String {
         let status = &self.state.mpd_status;
+        if self.display.hide_status_when_stopped && status.state == MpdState::Stop {
+            return String::new();
+        }
         let state = match status.state {
             MpdState::Stop => "Stopped",
             MpdState::Play => "Playing",
             MpdState::Pause => "Paused",
         };
-        let times = status.time.as_ref().map(|(current, total)| {
-            format!(
-                "{} / {}",
-                Self::fmt_duration(current),
-                Self::fmt_duration(total)
+        let state = match status.song {
+            // MPD's positions are 0-indexed.
+            Some(place) if self.display.show_queue_position && status.queue_len > 0 => {
+                format!("{} [{}/{}]", state, place.pos + 1, status.queue_len)
+            }
+            _ => state.to_string(),
+        };
+        let times = status.time.as_ref().map(|(current, total)| {
+            format!(
+                "{} / {}",
+                Self::fmt_duration(current),
+                Self::fmt_duration(total)
+            )
+        });
+
+        let desc = match times {
+            Some(times) => format!("{} - {}", state, times),
+            None => state,
+        };
+        let desc = match self.bitrate_desc() {
+            Some(bitrate) if self.display.status_show_bitrate => format!("{} - {}", desc, bitrate),
+            _ => desc,
+        };
+        let desc = normalize(&desc, self.display.unicode_normalize);
+        match self.display.max_title_length {
+            Some(max_width) => truncate_width(desc, max_width),
+            None => desc,
+        }
+    }
+
+    /// The bitrate of the playing song, e.g. "320 kbps". Kilobits are used even above 1000 to
+    /// match other MPD clients.
+    fn bitrate_desc(&self) -> Option<String> {
+        let status = &self.state.mpd_status;
+        match (status.bitrate?, status.audio) {
+            (0, Some(audio)) if audio.bits > 16 => Some("LOSSLESS".to_owned()),
+            (0, _) => None,
+            (bitrate, _) => Some(format!("{} kbps", bitrate)),
+        }
+    }
+
+    /// The status is stale if it has not been successfully refreshed for more than two poll
+    /// intervals, e.g. because MPD is unreachable, not counting time the fetching thread had
+    /// the connection.
+    fn status_is_stale(&self) -> bool {
+        let now = self.client_lent_at.unwrap_or_else(Instant::now);
+        match self.last_successful_status {
+            None => true,
+            Some(last) => now.saturating_duration_since(last) > 2 * self.status_refresh_period(),
+        }
+    }
+
+    /// Moves the last successful status forward by how long the fetching thread had the MPD
+    /// connection, now that it's been handed back.
+    fn resume_status_clock(&mut self) {
+        if let Some(lent_at) = self.client_lent_at.take() {
+            let lent_for = lent_at.elapsed();
+            self.last_successful_status = self.last_successful_status.map(|last| last + lent_for);
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.intervals
+            .idle_timeout
+            .is_some_and(|timeout| self.last_activity.elapsed() >= timeout)
+    }
+
+    fn status_refresh_period(&self) -> Duration {
+        if self.is_idle() {
+            self.intervals.status_refresh_period * Self::IDLE_POLL_FACTOR
+        } else {
+            self.intervals.status_refresh_period
+        }
+    }
+
+    /// Draws `message` in a small box in the middle of `area`.
+    fn render_overlay(&self, message: &str, area: Rect, buf: &mut Buffer) {
+        let width = (message.chars().count() + (HORIZ_BORDER_WIDTH + HORIZ_PADDING) * 2) as u16;
+        let height = (1 + VERT_BORDER_WIDTH * 2) as u16;
+        let overlay_area = Rect {
+            width: width.min(area.width),
+            height: height.min(area.height),
+            x: area.x + area.width.saturating_sub(width) / 2,
+            y: area.y + area.height.saturating_sub(height) / 2,
+        };
+
+        let dim_style = Style::default().add_modifier(Modifier::DIM);
+        Clear.render(overlay_area, buf);
+        Paragraph::new(Span::styled(message, dim_style))
+            .centered()
+            .block(
+                Block::bordered()
+                    .border_set(self.theme.border_set)
+                    .border_style(dim_style),
             )
-        });
+            .render(overlay_area, buf);
+    }
 
-        match times {
-            Some(times) => format!("{} - {}", state, times),
-            None => state.to_string(),
+    /// Splits the status bar, if enabled, off the bottom of the terminal area, shrinking it to
+    /// leave the art at least `MIN_ART_HEIGHT` rows.
+    fn split_status_bar(&self, area: Rect) -> (Rect, Option<Rect>) {
+        let status_bar_height = rows_beside_art(self.display.status_bar_height, area.height);
+        if status_bar_height == 0 {
+            return (area, None);
+        }
+        let [main_area, status_area] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(status_bar_height)])
+                .areas(area);
+        (main_area, Some(status_area))
+    }
+
+    /// Splits `area` into the areas of the art, and of the lyrics, previous art and
+    /// visualizer, if they're shown. Rows above the album art area are left free for wrapped
+    /// title lines, as many as fit while leaving the art `MIN_ART_HEIGHT` rows.
+    fn split_area(&self, area: Rect) -> (Rect, Option<Rect>, Option<Rect>, Option<Rect>) {
+        let area = self.split_status_bar(area).0;
+        let (art_area, lyrics_area) = if self.display.scroll_lyrics {
+            let [art_area, lyrics_area] =
+                Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                    .areas(area);
+            (art_area, Some(lyrics_area))
+        } else {
+            (area, None)
+        };
+        #[cfg(feature = "visualizer")]
+        let (art_area, visualizer_area) = if self.visualizer.is_some() {
+            let [art_area, visualizer_area] =
+                Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)])
+                    .areas(art_area);
+            (art_area, Some(visualizer_area))
+        } else {
+            (art_area, None)
+        };
+        #[cfg(not(feature = "visualizer"))]
+        let visualizer_area = None;
+        let title_rows = if self.display.status_bar_height == 0 {
+            rows_beside_art(self.display.title_lines as u16 - 1, art_area.height)
+        } else {
+            0
+        };
+        let art_area = Rect {
+            y: art_area.y + title_rows,
+            height: art_area.height - title_rows,
+            ..art_area
+        };
+        if self.display.compare {
+            let [previous_area, art_area] =
+                Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(art_area);
+            (art_area, lyrics_area, Some(previous_area), visualizer_area)
+        } else {
+            (art_area, lyrics_area, None, visualizer_area)
+        }
+    }
+
+    fn render_previous_art(&self, area: Rect, buf: &mut Buffer) {
+        let dim_style = Style::default().add_modifier(Modifier::DIM);
+        let no_image: Text<'static> = Span::styled("No previous album", dim_style).into();
+        let text = match &self.state.previous_art {
+            Some((_, text)) => text,
+            None => &no_image,
+        };
+        let (paragraph_area, vert_padding) = self.paragraph_area(area, text);
+        let block = Block::bordered()
+            .border_set(self.theme.border_set)
+            .border_style(dim_style)
+            .title(Span::styled("Previous", dim_style));
+        self.create_paragraph(buf, paragraph_area, vert_padding, block, text);
+    }
+
+    fn render_playlist_art(&self, area: Rect, buf: &mut Buffer) {
+        let tiles = &self.state.playlist_tiles;
+        let dim_style = Style::default().add_modifier(Modifier::DIM);
+        for (tile, tile_area) in tiles.iter().zip(Self::grid_areas(area, tiles.len())) {
+            let is_current = self
+                .state
+                .current_song
+                .as_ref()
+                .is_some_and(|song| self.songs_in_same_dir(song, &tile.song));
+            let border_style = if is_current {
+                Style::default()
+                    .fg(self.theme.border_color.unwrap_or(Color::White))
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                dim_style
+            };
+            let album = song_tag(&tile.song, "album").unwrap_or("Unknown album");
+
+            let text = match &tile.art {
+                TileArt::Loading(_) => Span::styled("Fetching image", dim_style).into(),
+                TileArt::Done(None) => Span::styled("No image", dim_style).into(),
+                TileArt::Done(Some(text)) => text.clone(),
+            };
+            let inner_height = tile_area
+                .height
+                .saturating_sub(2 * VERT_BORDER_WIDTH as u16);
+            let top_padding = inner_height.saturating_sub(text.height() as u16) / 2;
+            Paragraph::new(text)
+                .centered()
+                .block(
+                    Block::bordered()
+                        .title(album)
+                        .border_set(self.theme.border_set)
+                        .border_style(border_style)
+                        .padding(Padding::top(top_padding)),
+                )
+                .render(tile_area, buf);
+        }
+    }
+
+    fn render_lyrics(&self, area: Rect, buf: &mut Buffer) {
+        let mut block = Block::bordered()
+            .title(" Lyrics ")
+            .border_set(self.theme.border_set);
+        if let Some(color) = self.theme.border_color {
+            block = block.border_style(Style::default().fg(color));
+        }
+
+        let dim_style = Style::default().add_modifier(Modifier::DIM);
+        let (lines, current_line): (Vec<Line>, Option<usize>) = match &self.state.lyrics {
+            None => (vec![Line::styled("No lyrics", dim_style)], None),
+            Some(Lyrics::Unsynced(lines)) => (
+                lines.iter().map(|line| Line::raw(line.as_str())).collect(),
+                None,
+            ),
+            Some(Lyrics::Synced(lines)) => {
+                let elapsed = self
+                    .state
+                    .mpd_status
+                    .time
+                    .map(|(elapsed, _)| elapsed)
+                    .unwrap_or_default();
+                let current_line = lines.iter().rposition(|(time, _)| *time <= elapsed);
+                let lines = lines
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, words))| match current_line {
+                        Some(current) if current == i => Line::styled(
+                            words.as_str(),
+                            Style::default().add_modifier(Modifier::BOLD),
+                        ),
+                        _ => Line::styled(words.as_str(), dim_style),
+                    })
+                    .collect();
+                (lines, current_line)
+            }
+        };
+
+        // Keep the current line in the middle of the panel.
+        let inner_height = area.height.saturating_sub(2 * VERT_BORDER_WIDTH as u16) as usize;
+        let scroll = current_line.map_or(0, |line| line.saturating_sub(inner_height / 2));
+        Clear.render(area, buf);
+        Paragraph::new(lines)
+            .centered()
+            .scroll((scroll as u16, 0))
+            .block(block)
+            .render(area, buf);
+    }
+
+    /// Lists the songs in the queue, scrolled to keep the playing song in view.
+    fn render_playlist(&self, area: Rect, buf: &mut Buffer) {
+        let current_pos = self.state.mpd_status.song.map(|place| place.pos as usize);
+        let items: Vec<ListItem> = self
+            .state
+            .queue
+            .iter()
+            .enumerate()
+            .map(|(i, song)| {
+                let mut desc = self.artist_title_desc(song);
+                if self.display.mark_current_playlist_song && Some(i) == current_pos {
+                    if let Some((elapsed, total)) = &self.state.mpd_status.time {
+                        desc = format!(
+                            "{} ({} / {})",
+                            desc,
+                            Self::fmt_duration(elapsed),
+                            Self::fmt_duration(total)
+                        );
+                    }
+                }
+                ListItem::new(desc)
+            })
+            .collect();
+
+        let mut block = Block::bordered()
+            .title(" Playlist ")
+            .border_set(self.theme.border_set);
+        if let Some(color) = self.theme.border_color {
+            block = block.border_style(Style::default().fg(color));
+        }
+        let mut list = List::new(items).block(block);
+        if self.display.mark_current_playlist_song {
+            list = list
+                .highlight_symbol("▶ ")
+                .highlight_style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .add_modifier(Modifier::REVERSED),
+                )
+                .highlight_spacing(HighlightSpacing::Always);
+        }
+        let mut list_state = ListState::default().with_selected(current_pos);
+
+        Clear.render(area, buf);
+        StatefulWidget::render(list, area, buf, &mut list_state);
+    }
+
+    fn render_help_overlay(&self, area: Rect, buf: &mut Buffer) {
+        let mut actions = vec![
+            (Action::Quit, "Quit"),
+            (Action::TogglePause, "Play or pause"),
+            (Action::Next, "Next song"),
+            (Action::Prev, "Previous song"),
+            (Action::SeekBackward, "Seek backward"),
+            (Action::SeekForward, "Seek forward"),
+            (Action::VolumeUp, "Volume up"),
+            (Action::VolumeDown, "Volume down"),
+            (Action::TogglePlaylist, "Show or hide the playlist"),
+            (Action::ToggleHelp, "Show or hide this help"),
+            (Action::Export, "Save the art to the current directory"),
+            (Action::Reconvert, "Fetch and convert the art again"),
+        ];
+        if self.conversion.zoom > 1.0 {
+            actions.extend([
+                (Action::ScrollLeft, "Scroll left"),
+                (Action::ScrollDown, "Scroll down"),
+                (Action::ScrollUp, "Scroll up"),
+                (Action::ScrollRight, "Scroll right"),
+            ]);
+        }
+        if self.display.enable_stickers {
+            actions.push((Action::Rate, "Rate the current song"));
+        }
+        #[cfg(debug_assertions)]
+        actions.push((Action::ToggleDebug, "Show or hide the debug overlay"));
+        let bindings: Vec<(String, &str)> = actions
+            .into_iter()
+            .map(|(action, desc)| (self.keymap.describe(action), desc))
+            .collect();
+
+        let key_width = bindings
+            .iter()
+            .map(|(key, _)| key.chars().count())
+            .max()
+            .unwrap_or(0);
+        let key_style = Style::default().add_modifier(Modifier::BOLD);
+        let lines: Vec<Line> = bindings
+            .into_iter()
+            .map(|(key, action)| {
+                Line::from(vec![
+                    Span::styled(format!("{:>width$}", key, width = key_width), key_style),
+                    Span::raw(format!("  {}", action)),
+                ])
+            })
+            .collect();
+
+        let text_width = lines.iter().map(Line::width).max().unwrap_or(0);
+        let width =
+            ((text_width + (HORIZ_BORDER_WIDTH + HORIZ_PADDING) * 2) as u16).min(area.width);
+        let height = ((lines.len() + VERT_BORDER_WIDTH * 2) as u16).min(area.height);
+        let overlay_area = Rect {
+            width,
+            height,
+            x: area.x + (area.width - width) / 2,
+            y: area.y + (area.height - height) / 2,
+        };
+
+        Clear.render(overlay_area, buf);
+        Paragraph::new(lines)
+            .block(
+                Block::bordered()
+                    .title(" Keys ")
+                    .border_set(self.theme.border_set)
+                    .padding(Padding::horizontal(HORIZ_PADDING as u16)),
+            )
+            .render(overlay_area, buf);
+    }
+
+    /// Shows the song title, artist and album, and the playback progress, each on its own line
+    /// as far as `area` has room, with the progress on the bottom line.
+    fn render_status_bar(&self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        if area.height == 0 {
+            return;
+        }
+        let mut lines: Vec<Line> = Vec::new();
+        match &self.state.current_song {
+            None => lines.push(Line::styled(
+                "No song playing",
+                Style::default().add_modifier(Modifier::DIM),
+            )),
+            Some(song) => {
+                let form = self.display.unicode_normalize;
+                let (artist, title) = self.song_artist_title(song);
+                lines.push(Line::styled(
+                    normalize(title.unwrap_or("Unknown song"), form),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ));
+                let artist = artist.unwrap_or("Unknown artist");
+                let line = match song_tag(song, "album") {
+                    Some(album) => format!("{} - {}", artist, album),
+                    None => artist.to_owned(),
+                };
+                lines.push(normalize(&line, form).into());
+            }
+        }
+
+        let gauge_area = Rect {
+            y: area.bottom() - 1,
+            height: 1,
+            ..area
+        };
+        for (line, y) in lines.iter().zip(area.top()..gauge_area.top()) {
+            buf.set_line(area.x, y, line, area.width);
+        }
+        let ratio = match self.state.mpd_status.time {
+            Some((elapsed, total)) if !total.is_zero() => {
+                (elapsed.as_secs_f64() / total.as_secs_f64()).clamp(0.0, 1.0)
+            }
+            _ => 0.0,
+        };
+        let mut gauge_style = Style::default();
+        if let Some(color) = self.theme.title_color {
+            gauge_style = gauge_style.fg(color);
+        }
+        let mut label = Line::from(self.status_desc());
+        if self.status_is_stale() {
+            label.spans.insert(
+                0,
+                Span::styled("[STALE] ", Style::default().fg(self.theme.warning_color)),
+            );
+        }
+        LineGauge::default()
+            .ratio(ratio)
+            .label(label)
+            .filled_style(gauge_style)
+            .render(gauge_area, buf);
+    }
+
+    /// Average frames per second over the last `FPS_WINDOW` frames, or zero if nothing has
+    /// been drawn in the last second.
+    fn fps(&self) -> f64 {
+        match (self.frame_times.front(), self.frame_times.back()) {
+            (Some(first), Some(last)) if last.elapsed() < Duration::from_secs(1) => {
+                let span = last.duration_since(*first).as_secs_f64();
+                if span > 0.0 {
+                    (self.frame_times.len() - 1) as f64 / span
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Draws the --compact-mode bar, e.g. "▶ Artist - Title [03:45 / 05:12] 80%vol", on the
+    /// last row of `area`.
+    fn render_compact(&self, area: Rect, buf: &mut Buffer) {
+        let status = &self.state.mpd_status;
+        let symbol = match status.state {
+            MpdState::Play => "▶",
+            MpdState::Pause => "⏸",
+            MpdState::Stop => "■",
+        };
+        let mut bar = format!("{} {}", symbol, self.song_desc());
+        if let Some((elapsed, total)) = &status.time {
+            bar.push_str(&format!(
+                " [{} / {}]",
+                Self::fmt_duration(elapsed),
+                Self::fmt_duration(total)
+            ));
         }
+        // MPD reports -1 if it has no mixer.
+        if status.volume >= 0 {
+            bar.push_str(&format!(" {}%vol", status.volume));
+        }
+        let mut style = Style::default();
+        if self.status_is_stale() {
+            style = style.fg(self.theme.warning_color);
+        }
+        let row = Rect {
+            y: area.bottom().saturating_sub(1),
+            height: area.height.min(1),
+            ..area
+        };
+        Paragraph::new(Span::styled(bar, style)).render(row, buf);
+    }
+
+    /// Shows the volume in a box near the bottom of `area`, wherever the art is.
+    fn render_volume_overlay(&self, volume: i8, area: Rect, buf: &mut Buffer) {
+        const BAR_WIDTH: usize = 10;
+        let message = if volume < 0 {
+            "Volume unavailable".to_owned()
+        } else {
+            let filled = (volume as usize * BAR_WIDTH + 50) / 100;
+            let bar = "█".repeat(filled) + &"░".repeat(BAR_WIDTH - filled);
+            match self.display.volume_display_style {
+                VolumeDisplayStyle::Bar => bar,
+                VolumeDisplayStyle::Number => format!("Volume {}%", volume),
+                VolumeDisplayStyle::Both => format!("{} {}%", bar, volume),
+            }
+        };
+        let height = (1 + VERT_BORDER_WIDTH * 2) as u16;
+        let bottom_area = Rect {
+            y: area.bottom().saturating_sub(height + 1).max(area.y),
+            height: height.min(area.height),
+            ..area
+        };
+        self.render_overlay(&message, bottom_area, buf);
+    }
+
+    fn render_fps_counter(&self, area: Rect, buf: &mut Buffer) {
+        let text = format!("FPS: {:.1}", self.fps());
+        let width = (text.len() as u16).min(area.width);
+        let dim_style = Style::default().add_modifier(Modifier::DIM);
+        buf.set_stringn(
+            area.right() - width,
+            area.top(),
+            text,
+            width as usize,
+            dim_style,
+        );
+    }
+
+    #[cfg(debug_assertions)]
+    fn render_debug_overlay(&self, area: Rect, buf: &mut Buffer) {
+        let last_status_update = match self.last_status_update {
+            None => "never".to_owned(),
+            Some(last) => format!("{:?} ago", last.elapsed()),
+        };
+        let song_file = match &self.state.current_song {
+            None => "none",
+            Some(song) => song.file.as_str(),
+        };
+        let lines: Vec<Line> = vec![
+            format!("img_state: {}", self.state.img_state).into(),
+            format!("last_status_update: {}", last_status_update).into(),
+            format!("viewport_area: {}", self.state.viewport_area).into(),
+            format!("current_song: {}", song_file).into(),
+            format!("font_aspect: {:.3}", self.font_aspect).into(),
+        ];
+
+        let text_width = lines.iter().map(Line::width).max().unwrap_or(0);
+        let width = ((text_width + HORIZ_BORDER_WIDTH * 2) as u16).min(area.width);
+        let height = ((lines.len() + VERT_BORDER_WIDTH * 2) as u16).min(area.height);
+        let overlay_area = Rect {
+            width,
+            height,
+            x: area.x + area.width - width,
+            y: area.y,
+        };
+
+        Clear.render(overlay_area, buf);
+        Paragraph::new(lines)
+            .block(Block::bordered().title(" Debug "))
+            .render(overlay_area, buf);
+    }
+
+    /// A diamond of `c`, as large as fits the viewport up to `PLACEHOLDER_MAX_ROWS` rows, to
+    /// show in place of missing art.
+    fn placeholder_art(&self, c: char, style: Style) -> Text<'static> {
+        const PLACEHOLDER_MAX_ROWS: usize = 15;
+        let viewable_height = (self.state.viewport_area.height as usize)
+            .saturating_sub((VERT_VIEWPORT_GAP + VERT_BORDER_WIDTH + VERT_PADDING) * 2);
+        // An odd number of rows, so the diamond has a middle row.
+        let rows = (viewable_height.clamp(3, PLACEHOLDER_MAX_ROWS) - 1) | 1;
+        let half = rows / 2;
+        let symbol = c.to_string();
+        // Each row is shifted by half a symbol and its space. Wide characters, such as most
+        // emoji, take two cells.
+        let pad_width = Span::raw(symbol.as_str()).width().div_ceil(2);
+        let lines: Vec<Line> = (0..rows)
+            .map(|row| {
+                let count = half + 1 - row.abs_diff(half);
+                let line = format!(
+                    "{}{}",
+                    " ".repeat((half + 1 - count) * pad_width),
+                    vec![symbol.as_str(); count].join(" ")
+                );
+                Line::styled(line, style)
+            })
+            .collect();
+        Text::from(lines)
     }
 
     fn exit(&mut self) {
         self.exit = true;
     }
 
-    fn create_paragraph(&self, buf: &mut Buffer, viewport_area: Rect, block: Block, text: &Text) {
+    /// Adds the song at `uri` to the end of the MPD queue.
+    pub fn enqueue(&mut self, uri: String) -> Result<()> {
+        let song = Song {
+            file: uri,
+            ..Default::default()
+        };
+        self.with_client(|client| client.push(&song))
+            .map_err(|err| format!("error adding {} to the queue: {}", song.file, err))?;
+        info!("added {} to the queue", song.file);
+        Ok(())
+    }
+
+    fn clear_queue(&mut self) {
+        match self.with_client(|client| client.clear().and_then(|()| client.stop())) {
+            Ok(()) => info!("cleared the queue"),
+            Err(err) => warn!("error clearing the queue: {:?}", err),
+        }
+    }
+
+    /// Runs `f` with the MPD connection, or with a new one if an album art fetch is using it.
+    fn with_client<T>(
+        &mut self,
+        f: impl FnOnce(&mut MpdClient) -> mpd::error::Result<T>,
+    ) -> mpd::error::Result<T> {
+        match self.client.as_mut() {
+            Some(client) => f(client),
+            None => f(&mut connect_mpd(self.addr, &self.connect)?),
+        }
+    }
+
+    /// Computes where the art block for `text` goes within `viewport_area`, and its vertical
+    /// padding.
+    fn paragraph_area(&self, viewport_area: Rect, text: &Text) -> (Rect, usize) {
         let (width, height, vert_padding) = if text.height() > 1 {
             // This is an image
             let width = (text.width() + (HORIZ_BORDER_WIDTH + HORIZ_PADDING) * 2) as u16;
             let height = (text.height() + (VERT_BORDER_WIDTH + VERT_PADDING) * 2) as u16;
-            (width, height, VERT_PADDING)
+            // Zoomed art can be larger than the viewport, and is scrolled within it.
+            (
+                width.min(viewport_area.width),
+                height.min(viewport_area.height),
+                VERT_PADDING,
+            )
         } else {
             // This is a message
-            let viewable_width = viewport_area.width as usize - HORIZ_VIEWPORT_GAP * 2;
-            let viewable_height = viewport_area.height as usize - VERT_VIEWPORT_GAP * 2;
-            let viewport_aspect = viewable_width as f64 * self.font_aspect / viewable_height as f64;
-            if viewport_aspect < 1.0 {
-                // Taller than it is wide; use width to form a square.
-                let width = viewable_width as u16;
-                let height = (width as f64 * self.font_aspect) as u16;
-                (width, height, (height as usize - 2 * VERT_BORDER_WIDTH - 1) / 2)
-            } else {
-                // Wider than it is tall; Use height to form a square
-                let height = viewable_height as u16;
-                let width = (height as f64 / self.font_aspect) as u16;
-                (width, height, viewable_height / 2 - VERT_BORDER_WIDTH - 2)
-            }
+            message_box_size(viewport_area, self.font_aspect)
         };
 
         let area = Rect {
             width,
             height,
-            x: (viewport_area.width - width) / 2,
-            y: (viewport_area.height - height) / 2,
+            x: viewport_area.x + (viewport_area.width - width) / 2,
+            y: viewport_area.y + (viewport_area.height - height) / 2,
         };
+        (area, vert_padding)
+    }
 
+    fn create_paragraph(
+        &self,
+        buf: &mut Buffer,
+        area: Rect,
+        vert_padding: usize,
+        block: Block,
+        text: &Text,
+    ) {
         let padding = Padding::symmetric(HORIZ_PADDING as u16, vert_padding as u16);
 
+        // Keep the background, if any, from showing through the padding.
+        Clear.render(area, buf);
+        // Inside the border, including the padding.
+        let inner = block.inner(area);
+        // Padding is left blank by the paragraph, so it's filled first and the text drawn over
+        // the middle.
+        if let Some(c) = self.display.art_border_padding_char {
+            let fill = c.to_string().repeat(inner.width as usize);
+            let style = Style::default().add_modifier(Modifier::DIM);
+            for y in inner.top()..inner.bottom() {
+                buf.set_stringn(inner.x, y, &fill, inner.width as usize, style);
+            }
+        }
+
+        let scroll = if text.height() > 1 {
+            (self.scroll_y as u16, self.scroll_x as u16)
+        } else {
+            (0, 0)
+        };
         Paragraph::new(text.clone())
             .centered()
+            .scroll(scroll)
             .block(block.padding(padding))
             .render(area, buf);
+
+        if let Some(watermark) = &self.display.watermark {
+            let width = (watermark.width() as u16).min(inner.width);
+            if inner.height > 0 && width > 0 {
+                let mut style = Style::default().add_modifier(Modifier::DIM);
+                if let Some(color) = self.display.watermark_color {
+                    style = style.fg(color);
+                }
+                let corner = Rect::new(inner.right() - width, inner.bottom() - 1, width, 1);
+                Paragraph::new(Span::styled(watermark.as_str(), style))
+                    .right_aligned()
+                    .render(corner, buf);
+            }
+        }
     }
 }
 
 impl Widget for &App {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let title_style = Style::default()
+        if self.display.compact_mode {
+            self.render_compact(area, buf);
+            return;
+        }
+        let no_img_style = Style::default().add_modifier(Modifier::DIM);
+        let no_image: Text<'static> = match self.display.art_placeholder {
+            Some(c) => self.placeholder_art(c, no_img_style),
+            None => Span::styled("No image", no_img_style).into(),
+        };
+        let converting_image: Text<'static> = Span::styled("Converting image", no_img_style).into();
+        let fetching_image: Text<'static> = Span::styled("Fetching image", no_img_style).into();
+        let error_text: Text<'static>;
+        let colored_text = match &self.state.img_state {
+            ImgState::Idle(Some((_, text))) => text,
+            ImgState::Animated(_, animation) => animation.current_frame(),
+            ImgState::Idle(None) => &no_image,
+            ImgState::Fetching(_) => &fetching_image,
+            ImgState::Converting(_) => &converting_image,
+            ImgState::Error(msg) => {
+                error_text =
+                    Span::styled(msg.clone(), no_img_style.fg(self.theme.error_color)).into();
+                &error_text
+            }
+        };
+        let recolored_text: Text<'static>;
+        let colored_text = match &self.state.img_state {
+            ImgState::Idle(Some(_)) | ImgState::Animated(..) if self.display.shuffle_art_colors => {
+                recolored_text = recolor_text(colored_text.clone(), self.state.art_color_seed);
+                &recolored_text
+            }
+            _ => colored_text,
+        };
+        let dimmed_text: Text<'static>;
+        let colored_text = match &self.state.img_state {
+            ImgState::Idle(Some(_)) | ImgState::Animated(..)
+                if self.display.dim_on_pause && self.state.mpd_status.state == MpdState::Pause =>
+            {
+                dimmed_text = apply_dim(colored_text.clone());
+                &dimmed_text
+            }
+            _ => colored_text,
+        };
+
+        #[cfg_attr(not(feature = "visualizer"), allow(unused_variables))]
+        let (art_area, lyrics_area, previous_area, visualizer_area) = self.split_area(area);
+        let (paragraph_area, vert_padding) = self.paragraph_area(art_area, colored_text);
+        let block_area = match self.display.playlist_art_max {
+            Some(_) => art_area,
+            None => paragraph_area,
+        };
+        // Leave room for the corners and the caps around the title.
+        let title_width = (block_area.width as usize).saturating_sub(HORIZ_BORDER_WIDTH * 2 + 2);
+
+        let mut title_style = Style::default()
             .add_modifier(Modifier::REVERSED)
             .add_modifier(Modifier::BOLD);
-        let song_desc: Vec<Span> = vec![
-            "".into(),
-            Span::styled(self.song_desc(), title_style),
-            "".into(),
-        ];
-        let state_desc: Vec<Span> = vec![
-            "".into(),
-            Span::styled(self.status_desc(), title_style),
-            "".into(),
+        let mut title_cap_style = Style::default();
+        if let Some(color) = self.theme.title_color {
+            title_style = title_style.fg(color);
+            title_cap_style = title_cap_style.fg(color);
+        }
+        let title_chars = title_width / self.display.title_scale;
+        let mut title_lines = if self.display.status_bar_height > 0 {
+            Vec::new()
+        } else if self.display.title_lines > 1 {
+            wrap_title(&self.song_desc(), title_chars, self.display.title_lines)
+        } else {
+            vec![self.marquee(self.song_desc(), title_chars)]
+        };
+        if self.display.title_scale == 2 {
+            for line in &mut title_lines {
+                *line = to_fullwidth(line);
+            }
+        }
+        let title_line = |text: String| -> Vec<Span> {
+            vec![
+                Span::styled("", title_cap_style),
+                Span::styled(text, title_style),
+                Span::styled("", title_cap_style),
+            ]
+        };
+        // The last line goes in the border, and any others are stacked above it.
+        let song_desc = title_line(title_lines.pop().unwrap_or_default());
+        let status_desc = self.status_desc();
+        let show_state_desc = !status_desc.is_empty() || self.status_is_stale();
+        let mut state_desc: Vec<Span> = vec![
+            Span::styled("", title_cap_style),
+            Span::styled(status_desc, title_style),
+            Span::styled("", title_cap_style),
         ];
+        if self.status_is_stale() {
+            let stale_style = title_style.fg(self.theme.warning_color);
+            state_desc.insert(1, Span::styled("[STALE] ", stale_style));
+        }
 
         let state_desc: Title = state_desc.into();
         let state_desc = state_desc
             .alignment(Alignment::Right)
             .position(Position::Bottom);
         let title: Title = song_desc.into();
-        let block = Block::bordered()
-            .title(title.alignment(Alignment::Left))
-            .title(state_desc)
-            .border_set(border::ROUNDED);
-
-        let no_img_style = Style::default().add_modifier(Modifier::DIM);
-        let no_image: Text<'static> = Span::styled("No image", no_img_style).into();
-        let converting_image: Text<'static> = Span::styled("Converting image", no_img_style).into();
-        let fetching_image: Text<'static> = Span::styled("Fetching image", no_img_style).into();
-        let colored_text = match &self.state.img_state {
-            ImgState::Idle(Some((_, text))) => text,
-            ImgState::Idle(None) => &no_image,
-            ImgState::Fetching(_) => &fetching_image,
-            ImgState::Converting(_) => &converting_image,
+        let mut block = Block::bordered().border_set(self.theme.border_set);
+        if self.display.status_bar_height == 0 {
+            block = block.title(title.alignment(Alignment::Left));
+            if show_state_desc {
+                block = block.title(state_desc);
+            }
+        }
+        if let Some(rating) = self.state.rating {
+            let stars = "★".repeat(rating as usize) + &"☆".repeat(5 - rating as usize);
+            let rating_desc: Title = vec![
+                Span::styled("", title_cap_style),
+                Span::styled(stars, title_style),
+                Span::styled("", title_cap_style),
+            ]
+            .into();
+            block = block.title(
+                rating_desc
+                    .alignment(Alignment::Left)
+                    .position(Position::Bottom),
+            );
+        }
+        if self.display.show_next_song && self.state.current_song.is_some() {
+            let next_desc: Title = vec![
+                Span::styled("", title_cap_style),
+                Span::styled(self.next_song_desc(), title_style),
+                Span::styled("", title_cap_style),
+            ]
+            .into();
+            block = block.title(
+                next_desc
+                    .alignment(Alignment::Left)
+                    .position(Position::Bottom),
+            );
+        }
+        let block = match self.theme.border_color {
+            Some(color) => block.border_style(Style::default().fg(color)),
+            None => block,
         };
 
-        self.create_paragraph(buf, area, block, colored_text);
+        self.render_background(area, buf);
+        if self.display.playlist_art_max.is_some() {
+            let inner_area = block.inner(art_area);
+            Clear.render(art_area, buf);
+            block.render(art_area, buf);
+            self.render_playlist_art(inner_area, buf);
+        } else if self.display.tiling_mode && colored_text.height() > 1 {
+            // The border and titles are drawn over the tiles, which show through inside it.
+            self.render_tiled_art(colored_text, area, buf);
+            block.render(paragraph_area, buf);
+        } else {
+            self.create_paragraph(buf, paragraph_area, vert_padding, block, colored_text);
+        }
+        if let Some(previous_area) = previous_area {
+            self.render_previous_art(previous_area, buf);
+        }
+        for (i, text) in title_lines.into_iter().rev().enumerate() {
+            let Some(y) = block_area.y.checked_sub(i as u16 + 1) else {
+                break;
+            };
+            buf.set_line(
+                block_area.x + HORIZ_BORDER_WIDTH as u16,
+                y,
+                &title_line(text).into(),
+                block_area
+                    .width
+                    .saturating_sub(HORIZ_BORDER_WIDTH as u16 * 2),
+            );
+        }
+        if self.show_playlist {
+            self.render_playlist(art_area, buf);
+        }
+        if self.rating_prompt {
+            self.render_overlay("Rate 1-5 (any other key cancels)", art_area, buf);
+        } else if self.clear_queue_prompt {
+            self.render_overlay(
+                "Stop and clear the queue before quitting? y/n (any other key cancels)",
+                art_area,
+                buf,
+            );
+        } else if self.is_idle() {
+            self.render_overlay("Paused (press any key)", art_area, buf);
+        }
+        #[cfg(feature = "visualizer")]
+        if let (Some(visualizer), Some(area)) = (&self.visualizer, visualizer_area) {
+            let mut block = Block::bordered()
+                .title(" Spectrum ")
+                .border_set(self.theme.border_set);
+            if let Some(color) = self.theme.border_color {
+                block = block.border_style(Style::default().fg(color));
+            }
+            visualizer.render(area, buf, block);
+        }
+        if let Some(lyrics_area) = lyrics_area {
+            self.render_lyrics(lyrics_area, buf);
+        }
+        if let Some(status_area) = self.split_status_bar(area).1 {
+            self.render_status_bar(status_area, buf);
+        }
+        if self.show_help {
+            self.render_help_overlay(area, buf);
+        }
+        if self.volume_changed_at.is_some() {
+            self.render_volume_overlay(self.state.mpd_status.volume, area, buf);
+        }
+        if self.display.fps_counter {
+            self.render_fps_counter(area, buf);
+        }
+        #[cfg(debug_assertions)]
+        if self.show_debug_overlay {
+            self.render_debug_overlay(area, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_beside_art_leaves_min_art_height() {
+        assert_eq!(rows_beside_art(3, 40), 3);
+        assert_eq!(rows_beside_art(20, 24), 24 - MIN_ART_HEIGHT);
+        assert_eq!(rows_beside_art(3, MIN_ART_HEIGHT), 0);
+        assert_eq!(rows_beside_art(3, 5), 0);
+    }
+
+    #[test]
+    fn wrap_title_wraps_words_and_truncates() {
+        assert_eq!(wrap_title("Artist - Title", 8, 3), ["Artist -", "Title"]);
+        assert_eq!(
+            wrap_title("Supercalifragilistic", 8, 3),
+            ["Supercal", "ifragili", "stic"]
+        );
+        assert_eq!(wrap_title("one two three four", 5, 2), ["one", "two…"]);
+        assert_eq!(wrap_title("", 5, 2), [""]);
+    }
+
+    #[test]
+    fn parse_crop_takes_four_margins() {
+        assert_eq!(
+            parse_crop("1:2:3:4"),
+            Ok(Crop {
+                top: 1,
+                right: 2,
+                bottom: 3,
+                left: 4
+            })
+        );
+        assert!(parse_crop("1:2:3").is_err());
+        assert!(parse_crop("1:2:3:-4").is_err());
+    }
+
+    #[test]
+    fn truncate_width_counts_columns() {
+        assert_eq!(truncate_width("short".to_owned(), 5), "short");
+        assert_eq!(truncate_width("too long".to_owned(), 5), "too …");
+        // Fullwidth characters take two columns each.
+        assert_eq!(truncate_width("ＡＢＣ".to_owned(), 5), "ＡＢ…");
+    }
+
+    #[test]
+    fn message_box_fits_viewport() {
+        for (width, height) in [(80, 24), (20, 24), (12, 6), (5, 2), (0, 0)] {
+            let viewport = Rect::new(3, 4, width, height);
+            let (box_width, box_height, _) = message_box_size(viewport, 2.0);
+            assert!(
+                box_width <= width && box_height <= height,
+                "{}x{}",
+                width,
+                height
+            );
+        }
+        assert_eq!(message_box_size(Rect::new(0, 0, 12, 6), 2.0), (12, 6, 0));
+    }
+
+    #[test]
+    fn args_are_consistent() {
+        use clap::CommandFactory;
+        Args::command().debug_assert();
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn mpd_tls_conflicts_with_ssh_tunnel() {
+        let args = ["viewer", "--mpd-tls", "--ssh-tunnel", "me@example.com"];
+        assert!(Args::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn braille_encode_raises_dots() {
+        assert_eq!(braille_encode(&[[false; 4]; 2]), '\u{2800}');
+        assert_eq!(braille_encode(&[[true; 4]; 2]), '\u{28ff}');
+        assert_eq!(
+            braille_encode(&[[true, false, false, false], [false; 4]]),
+            '⠁'
+        );
+        // Dots 7 and 8, in the bottom row.
+        assert_eq!(
+            braille_encode(&[[false, false, false, true], [false; 4]]),
+            '⡀'
+        );
+        assert_eq!(
+            braille_encode(&[[false; 4], [false, false, false, true]]),
+            '⢀'
+        );
+    }
+
+    #[test]
+    fn auto_crop_finds_each_border() {
+        let white = Rgb([255, 255, 255]);
+        let uniform = DynamicImage::ImageRgb8(RgbImage::from_pixel(6, 5, white));
+        assert_eq!(auto_crop(&uniform, 0), (0, 0, 0, 0));
+
+        // Art in columns 1 to 3 and rows 2 to 3, with one nearly white pixel in the border.
+        let img = RgbImage::from_fn(6, 5, |x, y| match (x, y) {
+            (1..=3, 2..=3) => Rgb([0, 0, 0]),
+            (5, 0) => Rgb([250, 250, 250]),
+            _ => white,
+        });
+        let img = DynamicImage::ImageRgb8(img);
+        assert_eq!(auto_crop(&img, 5), (2, 2, 1, 1));
+        assert_eq!(auto_crop(&img, 0), (0, 0, 1, 1));
+    }
+
+    #[test]
+    fn strip_ansi_removes_escape_sequences() {
+        assert_eq!(strip_ansi("\x1b[38;5;1mred\x1b[0m plain"), "red plain");
+        // A lone escape is dropped, and the character after it kept.
+        assert_eq!(strip_ansi("a\x1bb"), "ab");
+        // A sequence cut off before its final byte is dropped to the end.
+        assert_eq!(strip_ansi("a\x1b[38;5"), "a");
+    }
+
+    #[test]
+    fn iso8601_basic_formats_utc() {
+        assert_eq!(iso8601_basic(0), "19700101T000000Z");
+        assert_eq!(iso8601_basic(946684799), "19991231T235959Z");
+        assert_eq!(iso8601_basic(946684800), "20000101T000000Z");
+        assert_eq!(iso8601_basic(951827696), "20000229T123456Z");
+        assert_eq!(iso8601_basic(1709164800), "20240229T000000Z");
+    }
+
+    #[test]
+    fn parse_border_set_takes_eight_characters() {
+        let set = parse_border_set("double").unwrap();
+        assert_eq!(set.top_left, border::DOUBLE.top_left);
+        assert_eq!(set.horizontal_top, border::DOUBLE.horizontal_top);
+
+        let set = parse_border_set("a:b:c:d:e:f:g:h").unwrap();
+        assert_eq!(
+            [
+                set.top_left,
+                set.horizontal_top,
+                set.top_right,
+                set.vertical_right,
+                set.bottom_right,
+                set.horizontal_bottom,
+                set.bottom_left,
+                set.vertical_left,
+            ],
+            ["a", "b", "c", "d", "e", "f", "g", "h"]
+        );
+        assert!(parse_border_set("a:b:c:d:e:f:g").is_err());
+        assert!(parse_border_set("a:b:c:d:e:f:g:h:i").is_err());
+        assert!(parse_border_set("a:b:c:d:e:f:g:hh").is_err());
+        assert!(parse_border_set("a:b:c::e:f:g:h").is_err());
+    }
+
+    #[test]
+    fn parse_secs_rejects_what_a_duration_cannot_hold() {
+        assert_eq!(parse_secs("0"), Ok(0.0));
+        assert_eq!(parse_secs("2.5"), Ok(2.5));
+        for s in ["-1", "NaN", "inf", "1e300", "soon"] {
+            assert!(parse_secs(s).is_err(), "{}", s);
+        }
+        assert!(parse_positive_secs("0").is_err());
+        assert_eq!(parse_positive_secs("0.5"), Ok(0.5));
+        assert!(parse_positive_f64("inf").is_err());
     }
 }
@@ -0,0 +1,22 @@
+//! Album art from the Cover Art Archive, for --musicbrainz-art.
+
+use log::info;
+use mpd::Song;
+
+use crate::{http, song_tag, Result};
+
+/// Fetches the front cover of the release of `song` from the Cover Art Archive, if the song is
+/// tagged with its MusicBrainz release ID. Art larger than `max_bytes` is an error.
+pub fn fetch_art(song: &Song, max_bytes: usize) -> Result<Option<Vec<u8>>> {
+    // The Cover Art Archive is keyed by release, which is the album ID tag. MUSICBRAINZ_TRACKID
+    // identifies a recording, which can be on many releases, so it can't be looked up there.
+    let Some(release_id) = song_tag(song, "MUSICBRAINZ_ALBUMID") else {
+        return Ok(None);
+    };
+    let url = format!(
+        "https://coverartarchive.org/release/{}/front",
+        http::encode_query_value(release_id)
+    );
+    info!("fetching album art from {}", url);
+    http::get(&url, max_bytes).map(Some)
+}
@@ -0,0 +1,426 @@
+//! A minimal HTTP server for --listen, serving the latest snapshot of the app's state as JSON,
+//! and with --websocket, streaming it to WebSocket clients as it changes.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::Result;
+
+/// How long a client may take to send its request before the connection is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The JSON served at `/state`, replaced by the app whenever its state changes.
+pub type Snapshot = Arc<SharedState>;
+
+#[derive(Default)]
+pub struct SharedState {
+    current: Mutex<Published>,
+    changed: Condvar,
+}
+
+/// The state last published. The art is kept apart from the rest, which changes far more
+/// often, so that WebSocket clients are only sent it again when it changes.
+#[derive(Clone, Default)]
+struct Published {
+    /// The state without the art, and how many times it has changed.
+    status: (u64, Value),
+    /// The art as a data URL, and how many times it has changed.
+    art: (u64, Option<Arc<str>>),
+}
+
+impl SharedState {
+    /// Replaces the state, waking the WebSocket clients if it differs from the last. `status`
+    /// must serialize to a JSON object, to which the art is added as `image`.
+    pub fn publish(&self, status: &impl Serialize, art: Option<&str>) -> serde_json::Result<()> {
+        let status = serde_json::to_value(status)?;
+        let mut current = self.current.lock().unwrap();
+        let mut changed = false;
+        if current.status.1 != status {
+            current.status = (current.status.0 + 1, status);
+            changed = true;
+        }
+        if current.art.1.as_deref() != art {
+            current.art = (current.art.0 + 1, art.map(Arc::from));
+            changed = true;
+        }
+        if changed {
+            self.changed.notify_all();
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Published {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Waits until the state has changed since `seen`, or `timeout` has passed.
+    fn wait_for_change(&self, seen: &Published, timeout: Duration) -> Published {
+        let counts = (seen.status.0, seen.art.0);
+        let (current, _) = self
+            .changed
+            .wait_timeout_while(self.current.lock().unwrap(), timeout, |current| {
+                (current.status.0, current.art.0) == counts
+            })
+            .unwrap();
+        current.clone()
+    }
+}
+
+impl Published {
+    /// The whole state as JSON, with the art.
+    fn to_json(&self) -> String {
+        let mut state = self.status.1.clone();
+        if let Value::Object(fields) = &mut state {
+            fields.insert("image".to_owned(), self.art_json());
+        }
+        state.to_string()
+    }
+
+    fn art_json(&self) -> Value {
+        match &self.art.1 {
+            Some(url) => Value::String(url.to_string()),
+            None => Value::Null,
+        }
+    }
+}
+
+/// Starts serving `snapshot` on `addr` in a background thread, accepting WebSocket upgrades if
+/// `websocket` is set.
+pub fn spawn(addr: SocketAddr, snapshot: Snapshot, websocket: bool) -> Result<()> {
+    let listener =
+        TcpListener::bind(addr).map_err(|err| format!("error listening on {}: {}", addr, err))?;
+    info!("serving state on http://{}/state", addr);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("error accepting HTTP connection: {:?}", err);
+                    continue;
+                }
+            };
+            // Each connection gets its own thread, so that a slow client can't hold up others.
+            let snapshot = snapshot.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = handle_connection(stream, &snapshot, websocket) {
+                    warn!("error serving HTTP request: {:?}", err);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Answers a single HTTP/1.0 request and closes the connection, or streams the state to a
+/// WebSocket client until it goes away.
+fn handle_connection(
+    stream: TcpStream,
+    snapshot: &Snapshot,
+    websocket: bool,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut headers = HashMap::new();
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_owned());
+        }
+        header.clear();
+    }
+    debug!("HTTP request: {}", request_line.trim_end());
+
+    let is_upgrade = headers
+        .get("upgrade")
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/state")) if websocket && is_upgrade => {
+            let Some(key) = headers.get("sec-websocket-key") else {
+                return respond(&stream, "400 Bad Request", "text/plain", "Bad Request\n");
+            };
+            return accept_websocket(stream, key, snapshot.clone());
+        }
+        (Some("GET"), Some("/state")) => ("200 OK", "application/json", snapshot.get().to_json()),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not Found\n".to_owned()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method Not Allowed\n".to_owned(),
+        ),
+    };
+    respond(&stream, status, content_type, &body)
+}
+
+fn respond(
+    mut stream: &TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// How often a WebSocket connection with nothing new to send checks that the client is still
+/// there.
+const WEBSOCKET_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// Largest message accepted from a WebSocket client, which has no reason to send more than a
+/// ping.
+const MAX_CLIENT_FRAME_LEN: u64 = 64 * 1024;
+
+/// Completes the WebSocket handshake for the client that sent `key`, then sends it the whole
+/// state, followed by the state without the art each time it changes and `{"image": ...}`
+/// each time the art changes, until the client goes away.
+fn accept_websocket(stream: TcpStream, key: &str, snapshot: Snapshot) -> std::io::Result<()> {
+    let mut writer = &stream;
+    write!(
+        writer,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        websocket_accept(key)
+    )?;
+    writer.flush()?;
+    info!("WebSocket client connected from {:?}", stream.peer_addr());
+
+    // The client's frames are read on their own thread, which answers pings and closes the
+    // connection when the client asks to.
+    stream.set_read_timeout(None)?;
+    let stream = Arc::new(Mutex::new(stream));
+    let reader = stream.lock().unwrap().try_clone()?;
+    let reader_thread = {
+        let stream = stream.clone();
+        std::thread::spawn(move || read_client_frames(reader, &stream))
+    };
+
+    let mut seen = snapshot.get();
+    let mut result = write_frame(&stream, OPCODE_TEXT, seen.to_json().as_bytes());
+    while result.is_ok() && !reader_thread.is_finished() {
+        let current = snapshot.wait_for_change(&seen, WEBSOCKET_CHECK_PERIOD);
+        if current.art.0 != seen.art.0 {
+            let message = serde_json::json!({ "image": current.art_json() });
+            result = write_frame(&stream, OPCODE_TEXT, message.to_string().as_bytes());
+        }
+        if result.is_ok() && current.status.0 != seen.status.0 {
+            result = write_frame(
+                &stream,
+                OPCODE_TEXT,
+                current.status.1.to_string().as_bytes(),
+            );
+        }
+        seen = current;
+    }
+    if let Err(err) = result {
+        debug!("WebSocket client went away: {:?}", err);
+    }
+    let _ = stream.lock().unwrap().shutdown(Shutdown::Both);
+    Ok(())
+}
+
+/// The Sec-WebSocket-Accept value answering a handshake with Sec-WebSocket-Key `key`.
+fn websocket_accept(key: &str) -> String {
+    const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+    base64(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Reads frames from a WebSocket client until it closes the connection or goes away,
+/// answering pings with pongs and a close with a close. Anything else it sends is ignored.
+fn read_client_frames(mut reader: TcpStream, writer: &Mutex<TcpStream>) {
+    loop {
+        let (opcode, payload) = match read_frame(&mut reader) {
+            Ok(frame) => frame,
+            Err(err) => {
+                debug!("error reading from WebSocket client: {:?}", err);
+                return;
+            }
+        };
+        let result = match opcode {
+            OPCODE_PING => write_frame(writer, OPCODE_PONG, &payload),
+            OPCODE_CLOSE => {
+                debug!("WebSocket client closed the connection");
+                // The close is echoed with the client's status code, if it sent one.
+                let _ = write_frame(writer, OPCODE_CLOSE, &payload[..payload.len().min(2)]);
+                return;
+            }
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            debug!("error answering WebSocket client: {:?}", err);
+            return;
+        }
+    }
+}
+
+/// Reads one frame from a WebSocket client, and returns its opcode and unmasked payload.
+fn read_frame(reader: &mut impl Read) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    let opcode = header[0] & 0x0f;
+    let masked = header[1] & 0x80 != 0;
+    let len = match header[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > MAX_CLIENT_FRAME_LEN {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("WebSocket frame of {} bytes is too large", len),
+        ));
+    }
+    let mut mask = [0; 4];
+    if masked {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+/// Writes `payload` as a single unmasked WebSocket frame with `opcode`.
+fn write_frame(stream: &Mutex<TcpStream>, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+    let len = payload.len();
+    let mut frame = vec![0x80 | opcode];
+    if len < 126 {
+        frame.push(len as u8);
+    } else if len <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(payload);
+    let mut stream = stream.lock().unwrap();
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// The SHA-1 digest of `data`, as needed for the WebSocket handshake.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// Encodes `bytes` as standard base64, with padding.
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn websocket_accept_matches_rfc_6455() {
+        assert_eq!(
+            websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn read_frame_unmasks_payload() {
+        // A masked "Hello" from RFC 6455, section 5.7.
+        let frame = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let (opcode, payload) = read_frame(&mut &frame[..]).unwrap();
+        assert_eq!(opcode, OPCODE_TEXT);
+        assert_eq!(payload, b"Hello");
+    }
+}
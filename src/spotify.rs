@@ -0,0 +1,109 @@
+//! Album art from Spotify's Web API, for --spotify-art.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+use mpd::Song;
+
+use crate::{http, server::base64, song_tag, Result};
+
+const TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+const API_URL: &str = "https://api.spotify.com/v1";
+/// How long before Spotify says an access token expires to stop using it, so a request
+/// doesn't race its expiry.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Credentials for Spotify's API, and the access token they were last exchanged for.
+pub struct Spotify {
+    client_id: String,
+    client_secret: String,
+    token: Mutex<Option<Token>>,
+}
+
+struct Token {
+    access_token: String,
+    expires: Instant,
+}
+
+impl Spotify {
+    pub fn new(client_id: String, client_secret: String) -> Spotify {
+        Spotify {
+            client_id,
+            client_secret,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Fetches the largest image of the first album Spotify finds by the artist of `song` with
+    /// the same name as its album. Responses larger than `max_bytes` are an error.
+    pub fn fetch_art(&self, song: &Song, max_bytes: usize) -> Result<Option<Vec<u8>>> {
+        let Some(artist) = song_tag(song, "AlbumArtist").or(song.artist.as_deref()) else {
+            return Ok(None);
+        };
+        let Some(album) = song_tag(song, "Album") else {
+            return Ok(None);
+        };
+        let query = format!("album:{} artist:{}", album, artist);
+        let url = format!(
+            "{}/search?q={}&type=album&limit=1",
+            API_URL,
+            http::encode_query_value(&query)
+        );
+        let authorization = format!("Bearer {}", self.access_token(max_bytes)?);
+        let response = http::request(
+            "GET",
+            &url,
+            &[("Authorization", &authorization)],
+            &[],
+            max_bytes,
+        )?;
+        let results: serde_json::Value = serde_json::from_slice(&response)?;
+        // The images are listed from largest to smallest.
+        let Some(image_url) = results["albums"]["items"][0]["images"][0]["url"].as_str() else {
+            debug!("Spotify has no image for {} - {}", artist, album);
+            return Ok(None);
+        };
+        info!("fetching album art from {}", image_url);
+        http::get(image_url, max_bytes).map(Some)
+    }
+
+    /// A current access token, from the client credentials flow, reusing the last one until it
+    /// expires.
+    fn access_token(&self, max_bytes: usize) -> Result<String> {
+        let mut token = self.token.lock().unwrap();
+        if let Some(token) = token
+            .as_ref()
+            .filter(|token| token.expires > Instant::now())
+        {
+            return Ok(token.access_token.clone());
+        }
+        let credentials = base64(format!("{}:{}", self.client_id, self.client_secret).as_bytes());
+        let response = http::request(
+            "POST",
+            TOKEN_URL,
+            &[
+                ("Authorization", &format!("Basic {}", credentials)),
+                ("Content-Type", "application/x-www-form-urlencoded"),
+            ],
+            b"grant_type=client_credentials",
+            max_bytes,
+        )?;
+        let response: serde_json::Value = serde_json::from_slice(&response)?;
+        let access_token = response["access_token"]
+            .as_str()
+            .ok_or_else(|| format!("no access token from Spotify: {}", response))?
+            .to_owned();
+        let expires_in = response["expires_in"]
+            .as_u64()
+            .ok_or_else(|| format!("no expiry for the access token from Spotify: {}", response))?;
+        debug!("got a Spotify access token for {} seconds", expires_in);
+        *token = Some(Token {
+            access_token: access_token.clone(),
+            expires: Instant::now() + Duration::from_secs(expires_in).saturating_sub(EXPIRY_MARGIN),
+        });
+        Ok(access_token)
+    }
+}
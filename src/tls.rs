@@ -0,0 +1,83 @@
+//! TLS connections, with the `tls` feature, for fetching art from HTTPS-only web services and
+//! for --mpd-tls.
+
+use std::{net::TcpStream, path::Path, sync::Arc};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+};
+
+use crate::Result;
+
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Starts a TLS session with `host` over `stream`, verifying its certificate against the
+/// Mozilla root certificates built in by webpki-roots.
+pub fn connect(host: &str, stream: TcpStream) -> Result<TlsStream> {
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots())
+        .with_no_client_auth();
+    let name = ServerName::try_from(host.to_owned())?;
+    let conn = ClientConnection::new(Arc::new(config), name)?;
+    Ok(StreamOwned::new(conn, stream))
+}
+
+fn roots() -> RootCertStore {
+    RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+}
+
+/// The settings for connecting to MPD over TLS, shared by all of the app's connections.
+pub struct MpdTls {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+impl MpdTls {
+    /// Settings for connecting to MPD at `host`, trusting the PEM certificates at `ca` as well as
+    /// the built-in roots, and authenticating with the PEM certificate chain and private key at
+    /// `cert` and `key` if given.
+    pub fn new(
+        host: &str,
+        ca: Option<&Path>,
+        cert: Option<&Path>,
+        key: Option<&Path>,
+    ) -> Result<MpdTls> {
+        let mut roots = roots();
+        if let Some(ca) = ca {
+            for cert in read_certs(ca)? {
+                roots
+                    .add(cert)
+                    .map_err(|err| format!("error adding {}: {}", ca.display(), err))?;
+            }
+        }
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let config = match (cert, key) {
+            (Some(cert), Some(key)) => {
+                let chain = read_certs(cert)?;
+                let key = PrivateKeyDer::from_pem_file(key)
+                    .map_err(|err| format!("error reading {}: {}", key.display(), err))?;
+                builder.with_client_auth_cert(chain, key)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err("--tls-cert and --tls-key must be given together".into()),
+        };
+        Ok(MpdTls {
+            config: Arc::new(config),
+            server_name: ServerName::try_from(host.to_owned())?,
+        })
+    }
+
+    pub fn connect(&self, stream: TcpStream) -> Result<TlsStream> {
+        let conn = ClientConnection::new(self.config.clone(), self.server_name.clone())?;
+        Ok(StreamOwned::new(conn, stream))
+    }
+}
+
+/// The certificates in the PEM file at `path`.
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|err| format!("error reading {}: {}", path.display(), err))?;
+    Ok(certs)
+}
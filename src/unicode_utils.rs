@@ -0,0 +1,43 @@
+//! Conversions between Unicode forms of text.
+
+use clap::ValueEnum;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum NormalizationForm {
+    /// Canonical composition, as most text is written
+    #[default]
+    #[value(name = "NFC")]
+    Nfc,
+    /// Canonical decomposition, with accents as separate combining characters
+    #[value(name = "NFD")]
+    Nfd,
+    /// Compatibility composition, which also folds e.g. fullwidth and halfwidth forms
+    #[value(name = "NFKC")]
+    Nfkc,
+    /// Compatibility decomposition
+    #[value(name = "NFKD")]
+    Nfkd,
+}
+
+/// `s` in the normalization form `form`.
+pub fn normalize(s: &str, form: NormalizationForm) -> String {
+    match form {
+        NormalizationForm::Nfc => s.nfc().collect(),
+        NormalizationForm::Nfd => s.nfd().collect(),
+        NormalizationForm::Nfkc => s.nfkc().collect(),
+        NormalizationForm::Nfkd => s.nfkd().collect(),
+    }
+}
+
+/// `s` with its printable ASCII characters and spaces replaced by their fullwidth forms,
+/// which terminals draw two columns wide. Other characters are left as they are.
+pub fn to_fullwidth(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            ' ' => '\u{3000}',
+            '!'..='~' => char::from_u32(c as u32 - 0x21 + 0xFF01).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
@@ -0,0 +1,148 @@
+//! The --visualizer spectrum, computed from the audio MPD writes to a FIFO output.
+
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    widgets::{Block, Sparkline, Widget},
+};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+/// Number of samples the spectrum is computed from, about 46 ms at 44.1 kHz.
+const FFT_SIZE: usize = 2048;
+/// Range of the bars in decibels below full scale.
+const DYNAMIC_RANGE_DB: f32 = 60.0;
+
+pub struct Visualizer {
+    /// The most recent `FFT_SIZE` samples, mixed down to mono.
+    samples: Arc<Mutex<Vec<f32>>>,
+    /// Set when samples have arrived since the spectrum was last drawn.
+    updated: Arc<AtomicBool>,
+    fft: Arc<dyn Fft<f32>>,
+}
+
+impl Visualizer {
+    /// Starts reading audio from the FIFO at `path` in a background thread. The audio must be
+    /// 16-bit stereo, as with MPD's default FIFO format of 44100:16:2.
+    pub fn spawn(path: PathBuf) -> Visualizer {
+        let samples = Arc::new(Mutex::new(vec![0.0; FFT_SIZE]));
+        let updated = Arc::new(AtomicBool::new(false));
+        let (thread_samples, thread_updated) = (samples.clone(), updated.clone());
+        std::thread::spawn(move || {
+            let mut warned_missing = false;
+            loop {
+                // Opening blocks until MPD opens its end, and reading ends when MPD closes it,
+                // e.g. when playback stops, so the FIFO is opened again each time.
+                match read_fifo(&path, &thread_samples, &thread_updated) {
+                    Ok(()) => (),
+                    // Retried every second in case MPD creates it, but only reported once.
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound && warned_missing => {
+                        debug!("{} is still missing", path.display());
+                        std::thread::sleep(Duration::from_secs(1));
+                    }
+                    Err(err) => {
+                        warn!("error reading audio from {}: {:?}", path.display(), err);
+                        warned_missing |= err.kind() == std::io::ErrorKind::NotFound;
+                        std::thread::sleep(Duration::from_secs(1));
+                    }
+                }
+                thread_samples.lock().unwrap().fill(0.0);
+                thread_updated.store(true, Ordering::Relaxed);
+            }
+        });
+        Visualizer {
+            samples,
+            updated,
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+        }
+    }
+
+    /// The loudness of `bands` frequency bands, from 0 to `DYNAMIC_RANGE_DB`. The bands are
+    /// spaced logarithmically, so the bass isn't squeezed into the first few.
+    fn spectrum(&self, bands: usize) -> Vec<u64> {
+        let mut buffer: Vec<Complex<f32>> = {
+            let samples = self.samples.lock().unwrap();
+            samples
+                .iter()
+                .enumerate()
+                .map(|(i, &sample)| {
+                    // A Hann window, to keep the edges of the buffer from smearing the spectrum.
+                    let window = (std::f32::consts::PI * i as f32 / FFT_SIZE as f32)
+                        .sin()
+                        .powi(2);
+                    Complex::new(sample * window, 0.0)
+                })
+                .collect()
+        };
+        self.fft.process(&mut buffer);
+
+        // Skip the DC bin, and the mirrored upper half.
+        let bins = &buffer[1..FFT_SIZE / 2];
+        let edge = |band: usize| (bins.len() as f64).powf(band as f64 / bands as f64) as usize;
+        (0..bands)
+            .map(|band| {
+                let start = edge(band).saturating_sub(1);
+                let end = edge(band + 1).clamp(start + 1, bins.len());
+                let magnitude = bins[start..end]
+                    .iter()
+                    .map(|bin| bin.norm())
+                    .fold(0.0, f32::max);
+                // A full scale sine wave peaks at a quarter of FFT_SIZE after windowing.
+                let db = 20.0 * (magnitude / (FFT_SIZE as f32 / 4.0)).log10();
+                (db + DYNAMIC_RANGE_DB).clamp(0.0, DYNAMIC_RANGE_DB) as u64
+            })
+            .collect()
+    }
+
+    /// Whether samples have arrived since this was last called, so the spectrum has changed.
+    pub fn take_update(&self) -> bool {
+        self.updated.swap(false, Ordering::Relaxed)
+    }
+
+    /// Draws the current spectrum in `block`.
+    pub fn render(&self, area: Rect, buf: &mut Buffer, block: Block) {
+        let inner = block.inner(area);
+        block.render(area, buf);
+        Sparkline::default()
+            .data(&self.spectrum(inner.width as usize))
+            .max(DYNAMIC_RANGE_DB as u64)
+            .render(inner, buf);
+    }
+}
+
+/// Reads audio from the FIFO at `path` into `samples` until MPD closes it, setting `updated`
+/// after each chunk.
+fn read_fifo(path: &Path, samples: &Mutex<Vec<f32>>, updated: &AtomicBool) -> std::io::Result<()> {
+    let mut fifo = File::open(path)?;
+    info!("reading audio from {}", path.display());
+    // Whole frames of two 16-bit little-endian samples.
+    let mut chunk = [0; 4096];
+    loop {
+        match fifo.read_exact(&mut chunk) {
+            Ok(()) => (),
+            // MPD has closed its end.
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err),
+        }
+        let mono = chunk.chunks_exact(4).map(|frame| {
+            let left = i16::from_le_bytes([frame[0], frame[1]]) as f32;
+            let right = i16::from_le_bytes([frame[2], frame[3]]) as f32;
+            (left + right) / 2.0 / i16::MAX as f32
+        });
+        let mut samples = samples.lock().unwrap();
+        samples.drain(..chunk.len() / 4);
+        samples.extend(mono);
+        updated.store(true, Ordering::Relaxed);
+    }
+}